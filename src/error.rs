@@ -1,13 +1,18 @@
 //! # Errors related to Revault transactions and Scripts management
 
-use crate::transactions::INSANE_FEES;
+use crate::{
+    transactions::{INSANE_FEES, MAX_STANDARD_P2WSH_SCRIPT_SIZE},
+    txouts::MAX_OP_RETURN_DATA,
+};
 
+#[cfg(feature = "verify")]
 use bitcoinconsensus::Error as LibConsensusError;
 use miniscript::{
     bitcoin::{
         consensus::encode::Error as EncodeError,
         secp256k1,
         util::psbt::{Input as PsbtInput, Output as PsbtOutput},
+        Txid,
     },
     policy::compiler::CompilerError,
 };
@@ -70,12 +75,18 @@ impl error::Error for ScriptCreationError {}
 #[derive(PartialEq, Debug)]
 pub enum TxoutCreationError {
     InvalidScriptPubkeyType,
+    OpReturnTooLarge(usize),
 }
 
 impl fmt::Display for TxoutCreationError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Self::InvalidScriptPubkeyType => write!(f, "Invalid ScriptPubKey type"),
+            Self::OpReturnTooLarge(size) => write!(
+                f,
+                "OP_RETURN data too large: {} bytes while at most {} is standard",
+                size, MAX_OP_RETURN_DATA
+            ),
         }
     }
 }
@@ -103,6 +114,16 @@ pub enum TransactionCreationError {
     InsufficientFunds,
     /// Would result in negative fees or create a too small output
     FeerateTooHigh,
+    /// Would not pay more fees than the transaction to be replaced
+    FeerateTooLow,
+    /// A witness Script is larger than MAX_STANDARD_P2WSH_SCRIPT_SIZE
+    ScriptTooLarge(usize),
+    /// More than one null-data (OP_RETURN) output, which is not standard
+    MultipleOpReturn,
+    /// The transaction weight computation overflowed
+    WeightOverflow,
+    /// Attempting to spend an Unvault transaction with no output paying to the Unvault descriptor
+    MissingUnvaultTxOut,
 }
 
 impl fmt::Display for TransactionCreationError {
@@ -135,6 +156,22 @@ impl fmt::Display for TransactionCreationError {
                 "Feerate too high, can't afford without creating a too small \
                  output or having negative fees"
             ),
+            Self::FeerateTooLow => write!(
+                f,
+                "Feerate too low: would not pay more fees than the replaced transaction"
+            ),
+            Self::ScriptTooLarge(size) => write!(
+                f,
+                "Witness Script too large: {} bytes while at most {} is standard",
+                size, MAX_STANDARD_P2WSH_SCRIPT_SIZE
+            ),
+            Self::MultipleOpReturn => write!(f, "More than one OP_RETURN output"),
+            Self::WeightOverflow => write!(f, "Transaction weight computation overflow"),
+            Self::MissingUnvaultTxOut => write!(
+                f,
+                "Attempting to spend an Unvault transaction without an output paying to the \
+                 Unvault descriptor"
+            ),
         }
     }
 }
@@ -156,6 +193,8 @@ pub enum InputSatisfactionError {
         secp256k1::PublicKey,
         secp256k1::Message,
     ),
+    /// Trying to add a signature which is not DER-encoded with a SIGHASH_ALL type
+    InvalidSignatureEncoding(Vec<u8>),
 }
 
 impl fmt::Display for InputSatisfactionError {
@@ -172,6 +211,9 @@ impl fmt::Display for InputSatisfactionError {
                 "Invalid signature '{:x?}' for key '{:x?}' and sighash '{:x?}'",
                 &sig, &pk, &hash
             ),
+            Self::InvalidSignatureEncoding(sig) => {
+                write!(f, "Invalid signature encoding '{:x?}'", &sig)
+            }
         }
     }
 }
@@ -200,6 +242,19 @@ pub enum PsbtValidationError {
     PartiallyFinalized,
     InsaneAmounts,
     TransactionTooLarge,
+    InputAmountMismatch(usize, u64, u64),
+    InvalidFeeAmount(u64, u64),
+    InvalidCpfpOutput,
+    InconsistentLockTime(Txid, u32),
+    NonStandardOutput(usize),
+    MultipleOpReturn,
+    MixedUnvaultDescriptors,
+    InvalidUnvaultValue(u64),
+    CpfpNotFirst,
+    InvalidChainTransaction(Txid),
+    PrevoutMismatch(usize),
+    InvalidSigHashType(PsbtInput),
+    FinalizedInput(usize),
 }
 
 impl fmt::Display for PsbtValidationError {
@@ -256,12 +311,129 @@ impl fmt::Display for PsbtValidationError {
                 f,
                 "Transaction too large: satisfied it could be >400k weight units"
             ),
+            Self::InputAmountMismatch(index, expected, got) => write!(
+                f,
+                "Input '{}' spends '{}' sats but '{}' sats were expected",
+                index, got, expected
+            ),
+            Self::InvalidFeeAmount(expected, got) => write!(
+                f,
+                "Transaction pays '{}' sats of fees but '{}' sats were expected",
+                got, expected
+            ),
+            Self::InvalidCpfpOutput => write!(f, "Missing or invalid CPFP output"),
+            Self::InconsistentLockTime(txid, lock_time) => write!(
+                f,
+                "Transaction '{}' has a lock_time of '{}', higher than or not comparable to the Unvault's",
+                txid, lock_time
+            ),
+            Self::NonStandardOutput(index) => {
+                write!(f, "Output '{}' pays to a non-standard Script", index)
+            }
+            Self::MultipleOpReturn => write!(f, "More than one OP_RETURN output"),
+            Self::MixedUnvaultDescriptors => {
+                write!(
+                    f,
+                    "Input does not spend an output of the Unvault descriptor"
+                )
+            }
+            Self::InvalidUnvaultValue(expected) => write!(
+                f,
+                "Unvault output value does not match the expected '{}' sats",
+                expected
+            ),
+            Self::CpfpNotFirst => write!(f, "First output is not the CPFP output"),
+            Self::InvalidChainTransaction(txid) => write!(
+                f,
+                "Transaction '{}' of the chain doesn't match the descriptors",
+                txid
+            ),
+            Self::PrevoutMismatch(index) => write!(
+                f,
+                "Input at index '{}' doesn't spend the expected previous output",
+                index
+            ),
+            Self::InvalidSigHashType(i) => {
+                write!(f, "Signature hash type other than ALL in input: '{:#?}'", i)
+            }
+            Self::FinalizedInput(index) => {
+                write!(f, "Input at index '{}' is already finalized", index)
+            }
         }
     }
 }
 
 impl error::Error for PsbtValidationError {}
 
+/// A reason for which a Revault transaction would not be relayed by bitcoind with its default
+/// policy.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum StandardnessViolation {
+    /// The output at this index is dust
+    DustOutput(usize),
+    /// The output at this index pays to a Script that is not of a standard type
+    NonStandardOutput(usize),
+    /// The transaction weight, once satisfied, is larger than MAX_STANDARD_TX_WEIGHT
+    TooLarge(u64),
+    /// The feerate, in sats/vbyte, is lower than the minimum relay feerate
+    FeerateTooLow(u64),
+    /// No input signals for replaceability
+    NoRbfSignaling,
+}
+
+impl fmt::Display for StandardnessViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::DustOutput(index) => write!(f, "Output at index '{}' is dust", index),
+            Self::NonStandardOutput(index) => {
+                write!(
+                    f,
+                    "Output at index '{}' pays to a non-standard Script",
+                    index
+                )
+            }
+            Self::TooLarge(weight) => write!(f, "Transaction too large: '{}' weight units", weight),
+            Self::FeerateTooLow(feerate) => write!(
+                f,
+                "Feerate of '{}' sats/vbyte is below the minimum relay feerate",
+                feerate
+            ),
+            Self::NoRbfSignaling => write!(f, "Transaction does not signal for replaceability"),
+        }
+    }
+}
+
+impl error::Error for StandardnessViolation {}
+
+/// A reason for which an input of a Revault transaction cannot be finalized (yet).
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum FinalizeDiagnosis {
+    /// The PSBT input does not comport a witness_script field
+    MissingWitnessScript,
+    /// There is no signature at all for this input
+    MissingSignature,
+    /// The signatures present (first) can't satisfy the witness Script, which needs at least
+    /// (second) signatures. There may be enough signatures but for the wrong keys.
+    InsufficientSignatures(usize, usize),
+    /// The signatures could satisfy the witness Script, but not the relative timelock
+    TimelockNotMet,
+}
+
+impl fmt::Display for FinalizeDiagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingWitnessScript => write!(f, "Missing witness_script field in PSBT input"),
+            Self::MissingSignature => write!(f, "No signature for this input"),
+            Self::InsufficientSignatures(have, need) => write!(
+                f,
+                "Insufficient signatures: got '{}' but at least '{}' are needed",
+                have, need
+            ),
+            Self::TimelockNotMet => write!(f, "Relative timelock not met by the nSequence"),
+        }
+    }
+}
+
 /// Error when working with serialized Revault transactions
 #[derive(PartialEq, Debug)]
 pub enum TransactionSerialisationError {
@@ -318,7 +490,20 @@ pub enum Error {
     // FIXME: have upstream(s) derive PartialEq on Errors?
     /// Completion (PSBT finalizer role) of the Revault transaction failed.
     TransactionFinalisation(String),
+    /// Trying to finalize a Revault transaction that was already finalized.
+    AlreadyFinalized,
+    /// The operation requires a finalized Revault transaction.
+    NotFinalized,
+    /// The Emergency and Unvault Emergency transactions of a chain pay to different addresses.
+    MismatchedEmergencyAddress,
+    /// Trying to modify a Revault transaction that was already (partially) signed.
+    AlreadySigned,
+    /// The CPFP transaction doesn't spend the CPFP output of the transaction it's bumping.
+    InvalidCpfpChild,
+    /// The signature for this public key on this input index is invalid.
+    InvalidSignature(usize, secp256k1::PublicKey),
     /// The verification of the PSBT input against libbitcoinconsensus failed.
+    #[cfg(feature = "verify")]
     TransactionVerification(LibConsensusError),
     /// Error when working with serialized Revault transactions
     TransactionSerialisation(TransactionSerialisationError),
@@ -348,6 +533,7 @@ impl From<InputSatisfactionError> for Error {
     }
 }
 
+#[cfg(feature = "verify")]
 impl From<LibConsensusError> for Error {
     fn from(e: LibConsensusError) -> Self {
         Self::TransactionVerification(e)
@@ -373,12 +559,27 @@ impl fmt::Display for Error {
             Error::InputSatisfaction(ref e) => {
                 write!(f, "Revault input satisfaction error: '{}'", e)
             }
+            #[cfg(feature = "verify")]
             Error::TransactionVerification(ref e) => {
                 write!(f, "Revault transaction verification error: '{:?}'", e)
             }
             Error::TransactionFinalisation(ref e) => {
                 write!(f, "Revault transaction finalisation error: '{}'", e)
             }
+            Error::AlreadyFinalized => write!(f, "Revault transaction already finalized"),
+            Error::NotFinalized => write!(f, "Revault transaction not finalized"),
+            Error::AlreadySigned => write!(f, "Revault transaction already signed"),
+            Error::InvalidCpfpChild => write!(
+                f,
+                "CPFP transaction does not spend the CPFP output of the transaction to bump"
+            ),
+            Error::MismatchedEmergencyAddress => write!(
+                f,
+                "Emergency and Unvault Emergency transactions pay to different addresses"
+            ),
+            Error::InvalidSignature(index, ref pk) => {
+                write!(f, "Invalid signature for key '{}' on input '{}'", pk, index)
+            }
             Error::TransactionSerialisation(ref e) => {
                 write!(f, "Revault transaction serialisation error: '{}'", e)
             }
@@ -19,7 +19,7 @@ use miniscript::{
         hashes::{hash160, Hash},
         secp256k1,
         util::bip32,
-        Address, PublicKey,
+        Address, Network, PublicKey, Script,
     },
     descriptor::{DescriptorPublicKey, DescriptorTrait, Wildcard, WshInner},
     miniscript::{
@@ -959,6 +959,18 @@ impl EmergencyAddress {
         }
     }
 
+    /// Get the Emergency Address an output pays to from its `script_pubkey`, for instance to
+    /// display the destination of a parsed Emergency transaction. Will error if the Script
+    /// isn't a v0 P2WSH.
+    pub fn from_script(
+        script_pubkey: &Script,
+        network: Network,
+    ) -> Result<EmergencyAddress, ScriptCreationError> {
+        let address = Address::from_script(script_pubkey, network)
+            .ok_or(ScriptCreationError::BadParameters)?;
+        EmergencyAddress::from(address)
+    }
+
     /// Get the address
     pub fn address(&self) -> &Address {
         &self.0
@@ -1256,6 +1268,18 @@ mod tests {
         .expect("Valid wildcard xpubs");
         DerivedDepositDescriptor::from_str(&deposit_desc.to_string())
             .expect_err("FromStr on an xpub descriptor");
+        // The descriptors are displayed with their checksum, as expected by bitcoind's
+        // importdescriptors, and it is checked when parsing.
+        let deposit_desc_str = deposit_desc.to_string();
+        let checksum = deposit_desc_str.rsplit('#').next().unwrap();
+        assert_eq!(checksum.len(), 8);
+        let bad_checksum = if checksum.ends_with('q') { "p" } else { "q" };
+        let bad_desc_str = format!(
+            "{}{}",
+            &deposit_desc_str[..deposit_desc_str.len() - 1],
+            bad_checksum
+        );
+        assert!(DepositDescriptor::from_str(&bad_desc_str).is_err());
         let der_deposit_desc =
             DerivedDepositDescriptor::new(vec![raw_pk_a.clone(), raw_pk_b.clone()])
                 .expect("Derived pubkeys");
@@ -2,16 +2,22 @@ use crate::{
     error::*,
     scripts::*,
     transactions::{
-        utils, RevaultPresignedTransaction, RevaultTransaction, CANCEL_DEPOSIT_MIN_SATS,
-        INSANE_FEES, MAX_STANDARD_TX_WEIGHT,
+        utils, RevaultPresignedTransaction, RevaultTransaction, RevaultTransactionChain,
+        UnvaultTransaction, CANCEL_DEPOSIT_MIN_SATS, INSANE_FEES, MAX_STANDARD_TX_WEIGHT,
     },
     txins::*,
     txouts::*,
 };
 
-use miniscript::bitcoin::{
-    blockdata::constants::max_money, consensus::encode::Decodable,
-    util::psbt::PartiallySignedTransaction as Psbt, Amount, Network, OutPoint,
+use miniscript::{
+    bitcoin::{
+        blockdata::constants::max_money,
+        consensus::encode::Decodable,
+        secp256k1,
+        util::{bip32::ChildNumber, psbt::PartiallySignedTransaction as Psbt},
+        Amount, Network, OutPoint,
+    },
+    DescriptorTrait,
 };
 
 #[cfg(feature = "use-serde")]
@@ -37,6 +43,8 @@ impl CancelTransaction {
         deposit_descriptor: &DerivedDepositDescriptor,
         feerate: Amount,
     ) -> Result<CancelTransaction, TransactionCreationError> {
+        utils::check_witness_script_size(unvault_input.txout().witness_script())?;
+
         // First, create a dummy transaction to get its weight without Witness.
         let dummy_deposit_txo = DepositTxOut::new(Amount::from_sat(u64::MAX), deposit_descriptor);
         let dummy_tx = utils::create_psbt(unvault_input.clone(), dummy_deposit_txo)
@@ -125,4 +133,174 @@ impl CancelTransaction {
             prev_txout,
         )
     }
+
+    /// Get the transaction chain of the new vault created by this Cancel transaction. The Cancel
+    /// pays back to the deposit descriptor at the `derivation_index` of the vault it revaulted.
+    ///
+    /// Will error with an `InvalidOutputField` if the Cancel doesn't pay to the deposit descriptor
+    /// at this index, or if its output is too small to create a new vault.
+    pub fn next_chain<C: secp256k1::Verification>(
+        &self,
+        deposit_descriptor: &DepositDescriptor,
+        unvault_descriptor: &UnvaultDescriptor,
+        cpfp_descriptor: &CpfpDescriptor,
+        derivation_index: ChildNumber,
+        emer_address: EmergencyAddress,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<RevaultTransactionChain, Error> {
+        let der_deposit_descriptor = deposit_descriptor.derive(derivation_index, secp);
+        if !self.revaults_to(&der_deposit_descriptor) {
+            return Err(TransactionSerialisationError::from(
+                PsbtValidationError::InvalidOutputField(self.psbt().outputs[0].clone()),
+            )
+            .into());
+        }
+        let deposit_txin = self.deposit_txin(&der_deposit_descriptor);
+
+        RevaultTransactionChain::from_descriptors(
+            deposit_txin.outpoint(),
+            Amount::from_sat(deposit_txin.txout().txout().value),
+            deposit_descriptor,
+            unvault_descriptor,
+            cpfp_descriptor,
+            derivation_index,
+            emer_address,
+            secp,
+        )
+    }
+
+    /// Whether the (single) output of this Cancel transaction pays back to this deposit
+    /// descriptor. Always check this before signing a Cancel you did not create!
+    pub fn revaults_to(&self, deposit_descriptor: &DerivedDepositDescriptor) -> bool {
+        self.tx().output[0].script_pubkey == deposit_descriptor.inner().script_pubkey()
+    }
+
+    /// Whether the (single) input of this Cancel transaction spends an output of this Unvault
+    /// transaction. Useful to match a received Cancel to the Unvault it revaults.
+    pub fn is_cancel_of(&self, unvault_tx: &UnvaultTransaction) -> bool {
+        let prevout = self.tx().input[0].previous_output;
+        if prevout.txid != unvault_tx.txid() {
+            return false;
+        }
+
+        // The spent txo must be the one recorded in our PSBT input
+        unvault_tx.tx().output.get(prevout.vout as usize)
+            == self.psbt().inputs[0].witness_utxo.as_ref()
+    }
+
+    /// Check the Deposit output value of a (parsed) Cancel transaction is what we'd have
+    /// created for this Unvault txin at this `feerate` (in sats/WU). Useful to make sure a
+    /// received Cancel was not crafted to overpay fees.
+    pub fn check_fees(
+        &self,
+        unvault_input: &UnvaultTxIn,
+        feerate: Amount,
+    ) -> Result<(), PsbtValidationError> {
+        let total_weight = self
+            .tx()
+            .get_weight()
+            .checked_add(unvault_input.txout().max_sat_weight())
+            .ok_or(PsbtValidationError::InsaneAmounts)?;
+        let total_weight: u64 = total_weight.try_into().expect("usize in u64");
+        let expected_fees = feerate
+            .as_sat()
+            .checked_mul(total_weight)
+            .ok_or(PsbtValidationError::InsaneAmounts)?;
+
+        // We only have a single output, the deposit output.
+        let fees = unvault_input
+            .txout()
+            .txout()
+            .value
+            .checked_sub(self.tx().output[0].value)
+            .ok_or(PsbtValidationError::InsaneAmounts)?;
+        if fees != expected_fees {
+            return Err(PsbtValidationError::InvalidFeeAmount(expected_fees, fees));
+        }
+
+        Ok(())
+    }
+
+    /// Preview the output values of this transaction if its fees were raised to
+    /// `target_feerate` (in sats/WU). This does not modify the transaction.
+    pub fn simulate_feebump(
+        &self,
+        target_feerate: u64,
+    ) -> Result<Vec<u64>, TransactionCreationError> {
+        utils::revocation_feebump_values(self.psbt(), target_feerate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::*,
+        transactions::{
+            test_utils::{test_vault, TestVault},
+            tests_helpers::other_child_number,
+            utils, CpfpableTransaction, RevaultTransaction, EMER_TX_FEERATE, UNVAULT_TX_FEERATE,
+        },
+        txins::RevaultTxIn,
+    };
+
+    use miniscript::bitcoin::{util::psbt::PartiallySignedTransaction as Psbt, Amount};
+
+    #[test]
+    fn cancel_next_chain() {
+        let vault = test_vault();
+        let deposit_outpoint = TestVault::default_outpoint();
+        let chain = vault
+            .chain(deposit_outpoint, Amount::from_sat(100_000_000))
+            .unwrap();
+        let cancel_tx = chain.cancel_batch().feerate_20();
+
+        // The Cancel of a large enough vault creates a new one, with the usual feerates
+        let next_chain = cancel_tx
+            .next_chain(
+                &vault.deposit_descriptor,
+                &vault.unvault_descriptor,
+                &vault.cpfp_descriptor,
+                vault.child_number,
+                vault.emergency_address.clone(),
+                &vault.secp,
+            )
+            .unwrap();
+        assert_eq!(
+            next_chain.unvault().tx().input[0].previous_output,
+            cancel_tx
+                .deposit_txin(&vault.der_deposit_descriptor())
+                .outpoint()
+        );
+        assert_eq!(next_chain.unvault().max_feerate(), UNVAULT_TX_FEERATE);
+        // The revocation transactions pay exactly their feerate (in sats/WU) for their max weight
+        let feerate =
+            |psbt: &Psbt| utils::psbt_fees(psbt).unwrap().as_sat() / utils::max_weight(psbt);
+        assert_eq!(feerate(next_chain.cancel_batch().feerate_20().psbt()), 5);
+        assert_eq!(
+            feerate(next_chain.cancel_batch().feerate_1000().psbt()),
+            250
+        );
+        assert_eq!(feerate(next_chain.emergency().psbt()), EMER_TX_FEERATE);
+        assert_eq!(
+            feerate(next_chain.unvault_emergency().psbt()),
+            EMER_TX_FEERATE
+        );
+
+        // It must revault to the deposit descriptor at this very index
+        assert!(matches!(
+            cancel_tx.next_chain(
+                &vault.deposit_descriptor,
+                &vault.unvault_descriptor,
+                &vault.cpfp_descriptor,
+                other_child_number(vault.child_number),
+                vault.emergency_address.clone(),
+                &vault.secp,
+            ),
+            Err(Error::TransactionSerialisation(
+                TransactionSerialisationError::Validation(PsbtValidationError::InvalidOutputField(
+                    _
+                ))
+            ))
+        ));
+    }
 }
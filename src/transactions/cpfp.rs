@@ -212,6 +212,20 @@ impl CpfpTransaction {
         self.0
     }
 
+    // Used by the tests to sign the CPFP transaction.
+    #[cfg(any(test, feature = "fuzz"))]
+    pub(super) fn psbt_mut(&mut self) -> &mut Psbt {
+        &mut self.0
+    }
+
+    /// Whether all the inputs of this CPFP transaction were finalized.
+    pub fn is_finalized(&self) -> bool {
+        self.psbt()
+            .inputs
+            .iter()
+            .all(|psbtin| psbtin.final_script_witness.is_some())
+    }
+
     pub fn tx(&self) -> &Transaction {
         &self.psbt().global.unsigned_tx
     }
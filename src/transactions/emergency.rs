@@ -2,8 +2,8 @@ use crate::{
     error::*,
     scripts::*,
     transactions::{
-        utils, RevaultPresignedTransaction, RevaultTransaction, EMER_TX_FEERATE, INSANE_FEES,
-        MAX_STANDARD_TX_WEIGHT,
+        utils, RevaultPresignedTransaction, RevaultTransaction, EMER_OUTPUT_MIN_SATS,
+        EMER_TX_FEERATE, INSANE_FEES, MAX_STANDARD_TX_WEIGHT,
     },
     txins::*,
     txouts::*,
@@ -30,6 +30,7 @@ impl RevaultPresignedTransaction for EmergencyTransaction {}
 impl EmergencyTransaction {
     /// The first emergency transaction always spends a deposit output and pays to the Emergency
     /// Script.
+    /// It is created with a fixed feerate of [EMER_TX_FEERATE].
     /// Will error **only** when trying to spend a dust deposit.
     ///
     /// BIP174 Creator and Updater roles.
@@ -37,6 +38,40 @@ impl EmergencyTransaction {
         deposit_input: DepositTxIn,
         emer_address: EmergencyAddress,
     ) -> Result<EmergencyTransaction, TransactionCreationError> {
+        EmergencyTransaction::create(
+            deposit_input,
+            emer_address,
+            Amount::from_sat(EMER_TX_FEERATE),
+        )
+    }
+
+    /// Same as [EmergencyTransaction::new] but with a custom `feerate`, in sats/WU.
+    /// Will error with [TransactionCreationError::Dust] if the deposit cannot afford it while
+    /// leaving an Emergency output of at least [EMER_OUTPUT_MIN_SATS], with
+    /// [TransactionCreationError::FeerateTooHigh] if the fees overflow and with
+    /// [TransactionCreationError::InsaneFees] if they are larger than [INSANE_FEES].
+    ///
+    /// BIP174 Creator and Updater roles.
+    pub fn with_feerate(
+        deposit_input: DepositTxIn,
+        emer_address: EmergencyAddress,
+        feerate: Amount,
+    ) -> Result<EmergencyTransaction, TransactionCreationError> {
+        let emer_tx = EmergencyTransaction::create(deposit_input, emer_address, feerate)?;
+        if emer_tx.tx().output[0].value < EMER_OUTPUT_MIN_SATS {
+            return Err(TransactionCreationError::Dust);
+        }
+
+        Ok(emer_tx)
+    }
+
+    fn create(
+        deposit_input: DepositTxIn,
+        emer_address: EmergencyAddress,
+        feerate: Amount,
+    ) -> Result<EmergencyTransaction, TransactionCreationError> {
+        utils::check_witness_script_size(deposit_input.txout().witness_script())?;
+
         // First, create a dummy transaction to get its weight without Witness.
         let emer_txo = EmergencyTxOut::new(emer_address.clone(), Amount::from_sat(u64::MAX));
         let dummy_tx = utils::create_psbt(deposit_input.clone(), emer_txo)
@@ -50,10 +85,10 @@ impl EmergencyTransaction {
             .checked_add(deposit_input.txout().max_sat_weight())
             .expect("Weight computation bug");
         let total_weight: u64 = total_weight.try_into().expect("usize in u64");
-        let fees = EMER_TX_FEERATE
+        let fees = feerate
+            .as_sat()
             .checked_mul(total_weight)
-            .expect("Weight computation bug");
-        assert!(fees < INSANE_FEES);
+            .ok_or(TransactionCreationError::FeerateTooHigh)?;
 
         assert!(
             total_weight <= MAX_STANDARD_TX_WEIGHT as u64,
@@ -65,6 +100,10 @@ impl EmergencyTransaction {
         let emer_value = deposit_value
             .checked_sub(fees)
             .ok_or(TransactionCreationError::Dust)?;
+        // Nobody wants to pay 3k€ fees if we had a bug.
+        if fees > INSANE_FEES {
+            return Err(TransactionCreationError::InsaneFees);
+        }
         // The emer output is the single one
         if emer_value > max_money(Network::Bitcoin) {
             return Err(TransactionCreationError::InsaneAmounts);
@@ -103,4 +142,163 @@ impl EmergencyTransaction {
             vout: 0,
         }
     }
+
+    /// Get the size of this transaction, assuming a fully-satisfied input. If the transaction
+    /// is already finalized, returns the exact size in witness units. Otherwise computes the
+    /// maximum reasonable weight of a satisfaction.
+    pub fn max_weight(&self) -> u64 {
+        utils::max_weight(self.psbt())
+    }
+
+    /// Get the virtual size of this transaction, assuming a fully-satisfied input.
+    pub fn max_vsize(&self) -> u64 {
+        self.max_weight()
+            .checked_add(3)
+            .expect("Weight computation bug")
+            / 4
+    }
+
+    /// Preview the output values of this transaction if its fees were raised to
+    /// `target_feerate` (in sats/WU). This does not modify the transaction.
+    pub fn simulate_feebump(
+        &self,
+        target_feerate: u64,
+    ) -> Result<Vec<u64>, TransactionCreationError> {
+        utils::revocation_feebump_values(self.psbt(), target_feerate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EmergencyTransaction;
+    use crate::{
+        error::*,
+        transactions::{
+            test_utils::{test_vault, TestVault},
+            RevaultTransaction, EMER_OUTPUT_MIN_SATS, EMER_TX_FEERATE, INSANE_FEES,
+        },
+        txins::*,
+        txouts::*,
+    };
+
+    use miniscript::bitcoin::{blockdata::constants::COIN_VALUE, Amount};
+
+    #[test]
+    fn emergency_with_feerate() {
+        let vault = test_vault();
+        // Large enough for insane fees to be reached before exhausting the deposit
+        let deposit_txin =
+            vault.deposit_txin(TestVault::default_outpoint(), Amount::from_sat(COIN_VALUE));
+        let deposit_value = deposit_txin.txout().txout().value;
+        let emergency_tx =
+            EmergencyTransaction::new(deposit_txin.clone(), vault.emergency_address.clone())
+                .unwrap();
+        let emer_weight = emergency_tx.max_weight();
+
+        assert_eq!(
+            EmergencyTransaction::with_feerate(
+                deposit_txin.clone(),
+                vault.emergency_address.clone(),
+                Amount::from_sat(EMER_TX_FEERATE)
+            ),
+            Ok(emergency_tx)
+        );
+        assert_eq!(
+            EmergencyTransaction::with_feerate(
+                deposit_txin.clone(),
+                vault.emergency_address.clone(),
+                Amount::from_sat(100)
+            )
+            .unwrap()
+            .fees()
+            .as_sat(),
+            emer_weight * 100
+        );
+        assert_eq!(
+            EmergencyTransaction::with_feerate(
+                deposit_txin.clone(),
+                vault.emergency_address.clone(),
+                Amount::from_sat(u64::MAX)
+            ),
+            Err(TransactionCreationError::FeerateTooHigh)
+        );
+        assert_eq!(
+            EmergencyTransaction::with_feerate(
+                deposit_txin.clone(),
+                vault.emergency_address.clone(),
+                Amount::from_sat(INSANE_FEES / emer_weight + 1)
+            ),
+            Err(TransactionCreationError::InsaneFees)
+        );
+        // Fees larger than the deposit
+        assert_eq!(
+            EmergencyTransaction::with_feerate(
+                deposit_txin.clone(),
+                vault.emergency_address.clone(),
+                Amount::from_sat(deposit_value / emer_weight + 1)
+            ),
+            Err(TransactionCreationError::Dust)
+        );
+
+        // Just enough fees to leave less than the minimum Emergency output value
+        let deposit_txin = vault.deposit_txin(
+            TestVault::default_outpoint(),
+            Amount::from_sat(emer_weight * 1_000 + EMER_OUTPUT_MIN_SATS),
+        );
+        EmergencyTransaction::with_feerate(
+            deposit_txin.clone(),
+            vault.emergency_address.clone(),
+            Amount::from_sat(1_000),
+        )
+        .expect("Leaves exactly the minimum Emergency output value");
+        assert_eq!(
+            EmergencyTransaction::with_feerate(
+                deposit_txin,
+                vault.emergency_address.clone(),
+                Amount::from_sat(1_001)
+            ),
+            Err(TransactionCreationError::Dust)
+        );
+    }
+
+    #[test]
+    fn emergency_min_output() {
+        let vault = test_vault();
+        let emer_fees = EmergencyTransaction::new(
+            vault.deposit_txin(TestVault::default_outpoint(), Amount::from_sat(COIN_VALUE)),
+            vault.emergency_address.clone(),
+        )
+        .unwrap()
+        .fees()
+        .as_sat();
+
+        // The minimum Emergency output value is only enforced for a custom feerate, the default
+        // constructor only refuses to spend a dust deposit.
+        let deposit_txin = vault.deposit_txin(
+            TestVault::default_outpoint(),
+            Amount::from_sat(emer_fees + EMER_OUTPUT_MIN_SATS - 1),
+        );
+        let emergency_tx =
+            EmergencyTransaction::new(deposit_txin.clone(), vault.emergency_address.clone())
+                .unwrap();
+        assert_eq!(emergency_tx.tx().output[0].value, EMER_OUTPUT_MIN_SATS - 1);
+        assert_eq!(
+            EmergencyTransaction::with_feerate(
+                deposit_txin,
+                vault.emergency_address.clone(),
+                Amount::from_sat(EMER_TX_FEERATE)
+            ),
+            Err(TransactionCreationError::Dust)
+        );
+        assert_eq!(
+            EmergencyTransaction::new(
+                vault.deposit_txin(
+                    TestVault::default_outpoint(),
+                    Amount::from_sat(emer_fees - 1)
+                ),
+                vault.emergency_address.clone(),
+            ),
+            Err(TransactionCreationError::Dust)
+        );
+    }
 }
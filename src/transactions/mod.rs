@@ -8,23 +8,34 @@
 use crate::{error::*, scripts::*, txins::*, txouts::*};
 use miniscript::{
     bitcoin::{
-        consensus::encode::Encodable,
+        consensus::encode::{self, Encodable},
+        hashes::{hash160, Hash},
         secp256k1,
         util::{
-            bip143::SigHashCache, bip32::ChildNumber, psbt::PartiallySignedTransaction as Psbt,
+            bip143::SigHashCache,
+            bip32::{self, ChildNumber},
+            psbt::PartiallySignedTransaction as Psbt,
         },
-        Address, Amount, Network, OutPoint, PublicKey as BitcoinPubKey, SigHash, SigHashType,
-        Transaction, Txid, Wtxid,
+        Address, Amount, Network, OutPoint, PublicKey as BitcoinPubKey, Script, SigHash,
+        SigHashType, Transaction, TxOut, Txid, Wtxid,
     },
-    DescriptorTrait,
+    miniscript::{limits::SEQUENCE_LOCKTIME_DISABLE_FLAG, satisfy::Older},
+    policy::Liftable,
+    BitcoinSig, Descriptor, DescriptorTrait, Miniscript, MiniscriptKey, Segwitv0,
 };
 
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     convert::TryInto,
     fmt,
 };
 
+#[cfg(feature = "use-serde")]
+use {
+    miniscript::bitcoin::hashes::hex::{FromHex, ToHex},
+    std::str::FromStr,
+};
+
 #[macro_use]
 mod utils;
 
@@ -58,6 +69,9 @@ pub const DEPOSIT_MIN_SATS: u64 = 500_000;
 /// The minimum value of a deposit UTxO created by a Cancel transaction.
 pub const CANCEL_DEPOSIT_MIN_SATS: u64 = 5_000;
 
+/// The minimum value of an Emergency UTxO created by an Emergency or Unvault Emergency transaction.
+pub const EMER_OUTPUT_MIN_SATS: u64 = 5_000;
+
 /// We can't safely error for insane fees on revaulting transactions, but we can for the unvault
 /// and the spend. This is 0.2BTC, or 3k€ currently.
 pub const INSANE_FEES: u64 = 20_000_000;
@@ -69,11 +83,32 @@ pub const TX_VERSION: i32 = 2;
 /// transactions.
 pub const TX_LOCKTIME: u32 = 0;
 
+/// nLockTime values below this are block heights, values above it are UNIX timestamps.
+///
+/// <https://github.com/bitcoin/bitcoin/blob/590e49ccf2af27c6c1f1e0eb8be3a4bf4d92ce8b/src/script/script.h#L39-L41>
+const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
 /// Maximum weight of a transaction to be relayed.
 ///
 /// <https://github.com/bitcoin/bitcoin/blob/590e49ccf2af27c6c1f1e0eb8be3a4bf4d92ce8b/src/policy/policy.h#L23-L24>
 pub const MAX_STANDARD_TX_WEIGHT: u32 = 400_000;
 
+/// Maximum size of a P2WSH witness Script to be relayed.
+///
+/// <https://github.com/bitcoin/bitcoin/blob/590e49ccf2af27c6c1f1e0eb8be3a4bf4d92ce8b/src/policy/policy.h#L42-L43>
+pub const MAX_STANDARD_P2WSH_SCRIPT_SIZE: usize = 3600;
+
+/// The default minimum feerate, in sats/vbyte, for a transaction to be relayed.
+///
+/// <https://github.com/bitcoin/bitcoin/blob/590e49ccf2af27c6c1f1e0eb8be3a4bf4d92ce8b/src/policy/policy.h#L47>
+pub const MIN_RELAY_FEERATE: u64 = 1;
+
+/// The default feerate, in sats/vbyte, a replacement transaction must pay for its own size on top
+/// of the fees of the transactions it replaces.
+///
+/// <https://github.com/bitcoin/bitcoin/blob/590e49ccf2af27c6c1f1e0eb8be3a4bf4d92ce8b/src/policy/policy.h>
+pub const INCREMENTAL_RELAY_FEERATE: u64 = 1;
+
 /// The min value for which we'll create a change in a CpfpTransaction. In other words: if the sum
 /// of the inputs minus the fees is less than CPFP_MIN_CHANGE, we'll throw everything in fees.
 pub const CPFP_MIN_CHANGE: u64 = 10_000;
@@ -131,6 +166,18 @@ pub trait RevaultTransaction: fmt::Debug + Clone + PartialEq {
         cache: &mut SigHashCache<&Transaction>,
     ) -> Result<SigHash, InputSatisfactionError>;
 
+    /// Get the sighash and the expected signature hash type of every input at once, by input
+    /// index. Allows a signer to get all the sighashes of a large Spend in a single round trip.
+    ///
+    /// NOTE: the type is always SIGHASH_ALL, the one the sighashes are computed for. PSBTs
+    /// specifying any other type are rejected at parsing time.
+    fn all_input_sighashes(&self) -> Result<Vec<(SigHash, SigHashType)>, InputSatisfactionError>;
+
+    /// Get the sighash for an input of a Revault transaction as raw bytes, for signers
+    /// that don't deal with the [SigHash] type.
+    /// NOTE: transactions are always signed with the SIGHASH_ALL flag.
+    fn signature_hash_bytes(&self, input_index: usize) -> Result<[u8; 32], InputSatisfactionError>;
+
     /// Add a signature in order to eventually satisfy this input.
     ///
     /// NOTE: this checks the signature. The expected signature type is ALL.
@@ -144,8 +191,44 @@ pub trait RevaultTransaction: fmt::Debug + Clone + PartialEq {
         secp: &secp256k1::Secp256k1<C>,
     ) -> Result<Option<Vec<u8>>, InputSatisfactionError>;
 
+    /// Add signatures for multiple keys on the same input, for instance for a participant who is
+    /// both a stakeholder and a cosigner.
+    ///
+    /// NOTE: this checks the signatures. The expected signature type is ALL. If any signature is
+    /// invalid, none of them is added.
+    ///
+    /// The BIP174 Signer role.
+    fn add_signatures<C: secp256k1::Verification>(
+        &mut self,
+        input_index: usize,
+        signatures: Vec<(secp256k1::PublicKey, secp256k1::Signature)>,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<(), InputSatisfactionError>;
+
+    /// Get the public keys of all the signatures on any of the inputs of this transaction.
+    fn signed_by(&self) -> BTreeSet<BitcoinPubKey>;
+
+    /// Get all the partial signatures of this transaction, tagged with the index of their input
+    /// and their public key. The signatures are DER-encoded with the sighash type byte appended.
+    fn all_partial_sigs(&self) -> Vec<(usize, BitcoinPubKey, Vec<u8>)>;
+
+    /// Get all the signatures of this transaction, without the rest of the PSBT.
+    fn signature_bundle(&self) -> SignatureBundle;
+
+    /// Add all the signatures of a [SignatureBundle] to this transaction.
+    ///
+    /// NOTE: this checks the signatures. The expected signature type is ALL. If any signature is
+    /// invalid, none of them is added.
+    fn apply_signature_bundle<C: secp256k1::Verification>(
+        &mut self,
+        bundle: &SignatureBundle,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<(), Error>;
+
     /// Check and satisfy the scripts, create the witnesses.
     ///
+    /// Will error with [Error::AlreadyFinalized] if the transaction was already finalized.
+    ///
     /// The BIP174 Input Finalizer role.
     fn finalize(
         &mut self,
@@ -157,15 +240,60 @@ pub trait RevaultTransaction: fmt::Debug + Clone + PartialEq {
     /// rid of the belt-and-suspenders checks.
     fn is_finalizable(&self, ctx: &secp256k1::Secp256k1<impl secp256k1::Verification>) -> bool;
 
+    /// Get the reason why each input that is not finalized yet could not be finalized, by input
+    /// index. Inputs that could be finalized are omitted.
+    ///
+    /// NOTE: this doesn't check the signatures, see [RevaultTransaction::verify_signatures].
+    fn diagnose_finalize(&self) -> Vec<(usize, FinalizeDiagnosis)>;
+
     /// Check if the transaction was already finalized.
     fn is_finalized(&self) -> bool;
 
+    /// Get the final witness of this input, if it was finalized and exists.
+    fn input_final_script_witness(&self, input_index: usize) -> Option<&Vec<Vec<u8>>>;
+
     /// Check the transaction is valid
+    #[cfg(feature = "verify")]
     fn is_valid(&self, ctx: &secp256k1::Secp256k1<impl secp256k1::Verification>) -> bool;
 
     /// Verify all PSBT inputs against libbitcoinconsensus
+    #[cfg(feature = "verify")]
     fn verify_inputs(&self) -> Result<(), Error>;
 
+    /// Verify a PSBT input against libbitcoinconsensus, under these specific script verification
+    /// `flags` (see the `VERIFY_*` constants of the bitcoinconsensus crate).
+    #[cfg(feature = "verify")]
+    fn verify_input_with_flags(&self, input_index: usize, flags: u32) -> Result<(), Error>;
+
+    /// Check all the signatures present in the PSBT inputs are valid, without finalizing. Useful
+    /// to spot a bad signer before trying to finalize.
+    ///
+    /// Will error with [Error::InvalidSignature] on the first invalid signature.
+    fn verify_signatures<C: secp256k1::Verification>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<(), Error>;
+
+    /// Check all the signatures present in the PSBT inputs are DER-encoded with a low S value,
+    /// as required for relay. Useful to reject a signature share before finalizing.
+    ///
+    /// Will error with [Error::InvalidSignature] on the first invalid or high-S signature.
+    fn check_sigs_low_s(&self) -> Result<(), Error>;
+
+    /// Whether all the signatures present in the PSBT inputs are DER-encoded with a low S value
+    /// (see [RevaultTransaction::check_sigs_low_s]).
+    fn all_sigs_low_s(&self) -> bool;
+
+    /// Check the value of each input's witness_utxo against the values we expect it to spend,
+    /// in order. Useful after parsing a transaction received from a third party.
+    fn check_input_amounts(&self, expected_amounts: &[u64]) -> Result<(), PsbtValidationError>;
+
+    /// Check each input spends the expected outpoint, and that its witness_utxo is the expected
+    /// previous output, in order. Unlike [RevaultTransaction::check_input_amounts], this also
+    /// checks the Script of the spent outputs. Useful for a signer to check a received
+    /// transaction against the coins it knows about, as the sighash commits to their values.
+    fn verify_prevouts(&self, expected: &[(OutPoint, TxOut)]) -> Result<(), PsbtValidationError>;
+
     /// Get the network-serialized (inner) transaction. You likely want to be sure
     /// the transaction [RevaultTransaction.is_finalized] before serializing it.
     ///
@@ -173,27 +301,129 @@ pub trait RevaultTransaction: fmt::Debug + Clone + PartialEq {
     /// [RevaultTransaction.finalize]).
     fn into_bitcoin_serialized(self) -> Vec<u8>;
 
+    /// Get the network-serialized (inner) transaction along with its txid, extracting the
+    /// transaction only once. Useful to broadcast a transaction and keep track of it.
+    ///
+    /// Will error with [Error::NotFinalized] if the transaction isn't finalized yet.
+    fn serialize_with_txid(&self) -> Result<(Vec<u8>, Txid), Error>;
+
     /// Get the BIP174-serialized (inner) transaction.
     fn as_psbt_serialized(&self) -> Vec<u8>;
 
+    /// Get the (inner) transaction serialized as a version 2 PSBT, as defined in
+    /// [BIP370](https://github.com/bitcoin/bips/blob/master/bip-0370.mediawiki).
+    fn as_psbt_v2_serialized(&self) -> Vec<u8>;
+
+    /// Get the length of the BIP174-serialized (inner) transaction, computed without serializing it.
+    fn psbt_serialized_len(&self) -> usize;
+
     /// Create a RevaultTransaction from a base64-encoded BIP174-serialized transaction.
     fn from_psbt_serialized(raw_psbt: &[u8]) -> Result<Self, TransactionSerialisationError>;
 
     /// Get the BIP174-serialized (inner) transaction encoded in base64.
     fn as_psbt_string(&self) -> String;
 
+    /// Get the base64-encoded BIP174-serialized transaction with all the information needed by
+    /// a watch-only wallet (such as bitcoind) to display and track it. The witness Script and
+    /// BIP32 derivations of the outputs paying to one of the given `descriptors` are included.
+    ///
+    /// The key origins of the BIP32 derivations are rewritten to be relative to the master keys,
+    /// as for the descriptors imported in the watch-only wallet. `fingerprints` maps the
+    /// fingerprint of each xpub of our descriptors to the fingerprint of its master key and its
+    /// derivation path from it. Will error if the origin of a key is missing.
+    fn to_watchonly_psbt(
+        &self,
+        descriptors: &[&Descriptor<DerivedPublicKey>],
+        fingerprints: &BTreeMap<bip32::Fingerprint, bip32::KeySource>,
+    ) -> Result<String, Error>;
+
     /// Create a RevaultTransaction from a base64-encoded BIP174-serialized transaction.
     fn from_psbt_str(psbt_str: &str) -> Result<Self, TransactionSerialisationError>;
 
     /// Sum of the inputs' value minus the sum of the outputs' value
     fn fees(&self) -> Amount;
 
+    /// Get the fees paid in excess because the weight of the inputs' witnesses was overestimated
+    /// at creation time, i.e. the share of the fees paid for the difference between the estimated
+    /// and the actual weight (rounded down).
+    ///
+    /// Will error with [Error::NotFinalized] if the transaction isn't finalized yet, or with a
+    /// [PsbtValidationError] if the witness Script can't be recovered from a final witness.
+    fn fee_overpayment(&self) -> Result<u64, Error>;
+
+    /// Get the amounts, size and feerate of this transaction at once, assuming fully-satisfied
+    /// inputs (see [RevaultTransaction::input_weights]).
+    fn fee_summary(&self) -> FeeSummary;
+
+    /// Whether the feerate of this transaction, assuming fully-satisfied inputs, is at least
+    /// `min_relay_feerate` (in sats/vbyte, see [MIN_RELAY_FEERATE]). A presigned transaction that
+    /// doesn't anymore needs to be fee-bumped to be relayed.
+    fn meets_min_relay_feerate(&self, min_relay_feerate: u64) -> bool;
+
+    /// Whether this transaction pays enough fees to replace the `replaced` transactions as per
+    /// BIP125 rules 3 and 4: at least their fees, plus its own virtual size (assuming
+    /// fully-satisfied inputs) at `incremental_relay_feerate` (in sats/vbyte, see
+    /// [INCREMENTAL_RELAY_FEERATE]).
+    fn meets_rbf_replacement(&self, replaced: &[&Self], incremental_relay_feerate: u64) -> bool;
+
+    /// Get the weight of the witness of each input, assuming they are fully satisfied. If an input
+    /// is already finalized, returns the exact weight of its witness.
+    fn input_weights(&self) -> Vec<u64>;
+
+    /// Whether this transaction signals for replaceability as per BIP125, ie if any of its inputs
+    /// has a sequence lower than 0xff_ff_ff_fe.
+    fn signals_rbf(&self) -> bool;
+
+    /// Whether the relative timelocks (in blocks) of all the inputs have matured at
+    /// `current_height`, given the height at which each spent output was confirmed (in the
+    /// order of the inputs). Returns `false` if there isn't exactly one height per input, or if
+    /// an input enables a time-based relative timelock.
+    fn is_spendable_at(&self, input_confirmation_heights: &[u32], current_height: u32) -> bool;
+
+    /// Whether this transaction pays to the same set of Scripts as `other`, regardless of the
+    /// amounts. Useful to check a fee-bumped replacement pays to the same destinations.
+    fn same_outputs_as(&self, other: &Self) -> bool;
+
+    /// Whether this transaction and `other` are different transactions spending at least one
+    /// common outpoint, ie they can't both be confirmed.
+    fn conflicts_with(&self, other: &Self) -> bool;
+
     /// Get the inner unsigned transaction id
     fn txid(&self) -> Txid;
 
     /// Get the inner unsigned transaction hash with witness data
     fn wtxid(&self) -> Wtxid;
 
+    /// Whether any output of this transaction pays to a Script that is not of a standard type
+    /// (P2PKH, P2SH, P2WPKH, P2WSH, P2TR or null data).
+    fn has_nonstandard_output(&self) -> bool;
+
+    /// Check all outputs of this transaction pay to a standard type of Script.
+    ///
+    /// Errors with the index of the first non-standard output.
+    fn check_outputs_standard(&self) -> Result<(), PsbtValidationError>;
+
+    /// Check this transaction against the standardness rules bitcoind would apply before
+    /// accepting it to its mempool: dust or non-standard outputs, weight, minimum relay feerate
+    /// and replaceability signaling. The weight and feerate assume fully-satisfied inputs.
+    ///
+    /// Returns all the violations, not only the first one.
+    fn check_standardness(&self) -> Result<(), Vec<StandardnessViolation>>;
+
+    /// Get the number of inputs of the inner transaction
+    fn num_inputs(&self) -> usize;
+
+    /// Get the number of outputs of the inner transaction
+    fn num_outputs(&self) -> usize;
+
+    /// Get the witness version and the witness program of each output of the inner
+    /// transaction, or `None` for outputs that don't pay to a witness program.
+    fn output_witness_programs(&self) -> Vec<Option<(u8, Vec<u8>)>>;
+
+    /// Get the address each output of the inner transaction pays to on this `network`, or `None`
+    /// for outputs that don't pay to an address (such as OP_RETURN outputs).
+    fn output_addresses(&self, network: Network) -> Vec<Option<Address>>;
+
     /// Get a reference to the inner transaction
     fn tx(&self) -> &Transaction;
 
@@ -205,6 +435,71 @@ pub trait RevaultTransaction: fmt::Debug + Clone + PartialEq {
     fn into_tx(self) -> Transaction;
 }
 
+/// A summary of the fees paid by a Revault transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeSummary {
+    /// Sum of the inputs' value
+    pub total_in: Amount,
+    /// Sum of the outputs' value
+    pub total_out: Amount,
+    /// The fees paid, `total_in - total_out`
+    pub absolute_fee: Amount,
+    /// The virtual size of the transaction, assuming fully-satisfied inputs
+    pub vsize: u64,
+    /// The feerate in sats/vbyte, rounded down
+    pub feerate_vb: u64,
+}
+
+/// The signatures of a Revault transaction, by input index and public key.
+///
+/// Signatures are DER-encoded with the sighash type appended, as in a PSBT. This is much more
+/// compact than the whole PSBT to exchange between participants.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SignatureBundle(BTreeMap<(usize, BitcoinPubKey), Vec<u8>>);
+
+impl SignatureBundle {
+    /// Create a bundle out of signatures indexed by (input index, public key)
+    pub fn new(signatures: BTreeMap<(usize, BitcoinPubKey), Vec<u8>>) -> SignatureBundle {
+        SignatureBundle(signatures)
+    }
+
+    /// Get the signatures indexed by (input index, public key)
+    pub fn signatures(&self) -> &BTreeMap<(usize, BitcoinPubKey), Vec<u8>> {
+        &self.0
+    }
+}
+
+#[cfg(feature = "use-serde")]
+impl serde::Serialize for SignatureBundle {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // FIXME: the windows CI build is preventing us from using the 'use-serde' feature of
+        // rust-bitcoin.
+        serializer.collect_seq(
+            self.0
+                .iter()
+                .map(|((index, pubkey), sig)| (index, pubkey.to_string(), sig.to_hex())),
+        )
+    }
+}
+
+#[cfg(feature = "use-serde")]
+impl<'de> serde::Deserialize<'de> for SignatureBundle {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let entries = Vec::<(usize, String, String)>::deserialize(deserializer)?;
+        entries
+            .into_iter()
+            .map(|(index, pubkey, sig)| {
+                let pubkey = BitcoinPubKey::from_str(&pubkey).map_err(D::Error::custom)?;
+                let sig = Vec::<u8>::from_hex(&sig).map_err(D::Error::custom)?;
+                Ok(((index, pubkey), sig))
+            })
+            .collect::<Result<_, _>>()
+            .map(SignatureBundle)
+    }
+}
+
 /// A Revault transaction which is signed in advance and whose signatures are exchanged by
 /// the participants.
 /// Contains a single transaction input.
@@ -292,6 +587,18 @@ impl<T: inner_mut::PrivateInnerMut + fmt::Debug + Clone + PartialEq> RevaultTran
         Ok(cache.signature_hash(input_index, &witscript, prev_txo.value, SigHashType::All))
     }
 
+    fn all_input_sighashes(&self) -> Result<Vec<(SigHash, SigHashType)>, InputSatisfactionError> {
+        let mut cache = SigHashCache::new(self.tx());
+        (0..self.psbt().inputs.len())
+            .map(|i| Ok((self.signature_hash_cached(i, &mut cache)?, SigHashType::All)))
+            .collect()
+    }
+
+    fn signature_hash_bytes(&self, input_index: usize) -> Result<[u8; 32], InputSatisfactionError> {
+        self.signature_hash(input_index)
+            .map(|sighash| sighash.into_inner())
+    }
+
     fn add_signature<C: secp256k1::Verification>(
         &mut self,
         input_index: usize,
@@ -358,10 +665,99 @@ impl<T: inner_mut::PrivateInnerMut + fmt::Debug + Clone + PartialEq> RevaultTran
         Ok(psbtin.partial_sigs.insert(pubkey, rawsig))
     }
 
+    fn add_signatures<C: secp256k1::Verification>(
+        &mut self,
+        input_index: usize,
+        signatures: Vec<(secp256k1::PublicKey, secp256k1::Signature)>,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<(), InputSatisfactionError> {
+        // Operate on a copy to not end up with only part of the signatures on error
+        let mut tx = self.clone();
+        for (pubkey, signature) in signatures {
+            tx.add_signature(input_index, pubkey, signature, secp)?;
+        }
+
+        *self = tx;
+        Ok(())
+    }
+
+    fn signed_by(&self) -> BTreeSet<BitcoinPubKey> {
+        self.psbt()
+            .inputs
+            .iter()
+            .flat_map(|psbtin| psbtin.partial_sigs.keys().copied())
+            .collect()
+    }
+
+    fn all_partial_sigs(&self) -> Vec<(usize, BitcoinPubKey, Vec<u8>)> {
+        self.psbt()
+            .inputs
+            .iter()
+            .enumerate()
+            .flat_map(|(i, psbtin)| {
+                psbtin
+                    .partial_sigs
+                    .iter()
+                    .map(move |(pubkey, sig)| (i, *pubkey, sig.clone()))
+            })
+            .collect()
+    }
+
+    fn signature_bundle(&self) -> SignatureBundle {
+        SignatureBundle(
+            self.psbt()
+                .inputs
+                .iter()
+                .enumerate()
+                .flat_map(|(index, psbtin)| {
+                    psbtin
+                        .partial_sigs
+                        .iter()
+                        .map(move |(pubkey, sig)| ((index, *pubkey), sig.clone()))
+                })
+                .collect(),
+        )
+    }
+
+    fn apply_signature_bundle<C: secp256k1::Verification>(
+        &mut self,
+        bundle: &SignatureBundle,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<(), Error> {
+        // Operate on a copy to not end up with only part of the signatures on error
+        let mut tx = self.clone();
+
+        for ((input_index, pubkey), rawsig) in bundle.signatures() {
+            let signature = match rawsig.split_last() {
+                Some((sighash_type, der_sig))
+                    if *sighash_type == SigHashType::All.as_u32() as u8 =>
+                {
+                    secp256k1::Signature::from_der(der_sig).map_err(|_| {
+                        InputSatisfactionError::InvalidSignatureEncoding(rawsig.clone())
+                    })?
+                }
+                _ => {
+                    return Err(
+                        InputSatisfactionError::InvalidSignatureEncoding(rawsig.clone()).into(),
+                    )
+                }
+            };
+            tx.add_signature(*input_index, pubkey.key, signature, secp)?;
+        }
+
+        *self = tx;
+        Ok(())
+    }
+
     fn finalize(
         &mut self,
         ctx: &secp256k1::Secp256k1<impl secp256k1::Verification>,
     ) -> Result<(), Error> {
+        // The witness scripts were wiped, don't let miniscript fail with a confusing error.
+        if self.is_finalized() {
+            return Err(Error::AlreadyFinalized);
+        }
+
         // We could operate on a clone for state consistency in case of error. But we can only end
         // up in an inconsistent state if miniscript's interpreter checks pass but not
         // libbitcoinconsensus' one.
@@ -372,6 +768,7 @@ impl<T: inner_mut::PrivateInnerMut + fmt::Debug + Clone + PartialEq> RevaultTran
 
         // Miniscript's finalize does not check against libbitcoinconsensus. And we are better safe
         // than sorry when dealing with Script ...
+        #[cfg(feature = "verify")]
         self.verify_inputs()?;
 
         Ok(())
@@ -384,6 +781,67 @@ impl<T: inner_mut::PrivateInnerMut + fmt::Debug + Clone + PartialEq> RevaultTran
         miniscript::psbt::finalize(&mut self.psbt().clone(), ctx).is_ok()
     }
 
+    /// Get why each non-finalized input could not be finalized, by input index.
+    fn diagnose_finalize(&self) -> Vec<(usize, FinalizeDiagnosis)> {
+        let psbt = self.psbt();
+        let mut diagnoses = Vec::new();
+
+        for (i, psbtin) in psbt.inputs.iter().enumerate() {
+            if psbtin.final_script_witness.is_some() {
+                continue;
+            }
+            let witness_script = match psbtin.witness_script {
+                Some(ref script) => script,
+                None => {
+                    diagnoses.push((i, FinalizeDiagnosis::MissingWitnessScript));
+                    continue;
+                }
+            };
+            if psbtin.partial_sigs.is_empty() {
+                diagnoses.push((i, FinalizeDiagnosis::MissingSignature));
+                continue;
+            }
+
+            let ms: Miniscript<BitcoinPubKey, Segwitv0> = Miniscript::parse(witness_script)
+                .expect("Revault txins witness_script is created from a Miniscript");
+            // Keyed by hash as the parsed Miniscript may use pkh() fragments
+            let sigs: HashMap<hash160::Hash, (BitcoinPubKey, BitcoinSig)> = psbtin
+                .partial_sigs
+                .iter()
+                .filter_map(|(pubkey, rawsig)| {
+                    let (sighash_type, der_sig) = rawsig.split_last()?;
+                    let sig = secp256k1::Signature::from_der(der_sig).ok()?;
+                    let sighash_type = SigHashType::from_u32_standard(*sighash_type as u32).ok()?;
+                    Some((pubkey.to_pubkeyhash(), (*pubkey, (sig, sighash_type))))
+                })
+                .collect();
+
+            let sequence = psbt.global.unsigned_tx.input[i].sequence;
+            if ms.satisfy((&sigs, Older(sequence))).is_ok() {
+                continue;
+            }
+            // A disabled relative timelock makes the satisfier ignore it
+            if ms
+                .satisfy((&sigs, Older(SEQUENCE_LOCKTIME_DISABLE_FLAG)))
+                .is_ok()
+            {
+                diagnoses.push((i, FinalizeDiagnosis::TimelockNotMet));
+                continue;
+            }
+
+            let need = ms
+                .lift()
+                .expect("Revault Miniscripts are liftable")
+                .minimum_n_keys();
+            diagnoses.push((
+                i,
+                FinalizeDiagnosis::InsufficientSignatures(psbtin.partial_sigs.len(), need),
+            ));
+        }
+
+        diagnoses
+    }
+
     /// Check if the transaction was already finalized.
     fn is_finalized(&self) -> bool {
         for i in self.psbt().inputs.iter() {
@@ -396,7 +854,17 @@ impl<T: inner_mut::PrivateInnerMut + fmt::Debug + Clone + PartialEq> RevaultTran
         false
     }
 
+    /// Get the final witness of this input, if it was finalized and exists.
+    fn input_final_script_witness(&self, input_index: usize) -> Option<&Vec<Vec<u8>>> {
+        self.psbt()
+            .inputs
+            .get(input_index)?
+            .final_script_witness
+            .as_ref()
+    }
+
     /// Check the transaction is valid
+    #[cfg(feature = "verify")]
     fn is_valid(&self, ctx: &secp256k1::Secp256k1<impl secp256k1::Verification>) -> bool {
         if !self.is_finalized() {
             return false;
@@ -413,6 +881,7 @@ impl<T: inner_mut::PrivateInnerMut + fmt::Debug + Clone + PartialEq> RevaultTran
     }
 
     /// Verify all PSBT inputs against libbitcoinconsensus
+    #[cfg(feature = "verify")]
     fn verify_inputs(&self) -> Result<(), Error> {
         let ser_tx = self.clone().into_bitcoin_serialized();
 
@@ -429,6 +898,124 @@ impl<T: inner_mut::PrivateInnerMut + fmt::Debug + Clone + PartialEq> RevaultTran
         Ok(())
     }
 
+    #[cfg(feature = "verify")]
+    fn verify_input_with_flags(&self, input_index: usize, flags: u32) -> Result<(), Error> {
+        let utxo = self
+            .psbt()
+            .inputs
+            .get(input_index)
+            .ok_or(InputSatisfactionError::OutOfBounds)?
+            .witness_utxo
+            .as_ref()
+            .expect("A witness_utxo is always set");
+        let ser_tx = self.clone().into_bitcoin_serialized();
+
+        bitcoinconsensus::verify_with_flags(
+            utxo.script_pubkey.as_bytes(),
+            utxo.value,
+            &ser_tx,
+            input_index,
+            flags,
+        )?;
+
+        Ok(())
+    }
+
+    fn verify_signatures<C: secp256k1::Verification>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<(), Error> {
+        let mut cache = SigHashCache::new(self.tx());
+
+        for (i, psbtin) in self.psbt().inputs.iter().enumerate() {
+            if psbtin.partial_sigs.is_empty() {
+                continue;
+            }
+            let sighash = self.signature_hash_cached(i, &mut cache)?;
+            let sighash =
+                secp256k1::Message::from_slice(&sighash).expect("sighash is 32 a bytes hash");
+
+            for (pubkey, rawsig) in psbtin.partial_sigs.iter() {
+                let invalid_sig = || Error::InvalidSignature(i, pubkey.key);
+                let (sighash_type, der_sig) = rawsig.split_last().ok_or_else(invalid_sig)?;
+                if *sighash_type != SigHashType::All.as_u32() as u8 {
+                    return Err(invalid_sig());
+                }
+                let signature =
+                    secp256k1::Signature::from_der(der_sig).map_err(|_| invalid_sig())?;
+                secp.verify(&sighash, &signature, &pubkey.key)
+                    .map_err(|_| invalid_sig())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_sigs_low_s(&self) -> Result<(), Error> {
+        for (i, psbtin) in self.psbt().inputs.iter().enumerate() {
+            for (pubkey, rawsig) in psbtin.partial_sigs.iter() {
+                let invalid_sig = || Error::InvalidSignature(i, pubkey.key);
+                let (_, der_sig) = rawsig.split_last().ok_or_else(invalid_sig)?;
+                let signature =
+                    secp256k1::Signature::from_der(der_sig).map_err(|_| invalid_sig())?;
+
+                let mut normalized_sig = signature;
+                normalized_sig.normalize_s();
+                if normalized_sig != signature {
+                    return Err(invalid_sig());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn all_sigs_low_s(&self) -> bool {
+        self.check_sigs_low_s().is_ok()
+    }
+
+    fn check_input_amounts(&self, expected_amounts: &[u64]) -> Result<(), PsbtValidationError> {
+        let psbtins = &self.psbt().inputs;
+        if psbtins.len() != expected_amounts.len() {
+            return Err(PsbtValidationError::InvalidInputCount(psbtins.len()));
+        }
+
+        for (i, (psbtin, expected)) in psbtins.iter().zip(expected_amounts.iter()).enumerate() {
+            let value = psbtin
+                .witness_utxo
+                .as_ref()
+                .expect("A witness_utxo is always set")
+                .value;
+            if value != *expected {
+                return Err(PsbtValidationError::InputAmountMismatch(
+                    i, *expected, value,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn verify_prevouts(&self, expected: &[(OutPoint, TxOut)]) -> Result<(), PsbtValidationError> {
+        let psbt = self.psbt();
+        if psbt.inputs.len() != expected.len() {
+            return Err(PsbtValidationError::InvalidInputCount(psbt.inputs.len()));
+        }
+
+        let txins = psbt.global.unsigned_tx.input.iter();
+        for (i, ((txin, psbtin), (outpoint, txo))) in txins
+            .zip(psbt.inputs.iter())
+            .zip(expected.iter())
+            .enumerate()
+        {
+            if txin.previous_output != *outpoint || psbtin.witness_utxo.as_ref() != Some(txo) {
+                return Err(PsbtValidationError::PrevoutMismatch(i));
+            }
+        }
+
+        Ok(())
+    }
+
     /// Get the network-serialized (inner) transaction. You likely want to be sure
     /// the transaction [RevaultTransaction.is_finalized] before serializing it.
     ///
@@ -443,20 +1030,51 @@ impl<T: inner_mut::PrivateInnerMut + fmt::Debug + Clone + PartialEq> RevaultTran
         buf
     }
 
+    fn serialize_with_txid(&self) -> Result<(Vec<u8>, Txid), Error> {
+        if !self.is_finalized() {
+            return Err(Error::NotFinalized);
+        }
+
+        let tx = self.psbt().clone().extract_tx();
+        let mut buf = Vec::with_capacity(256);
+        tx.consensus_encode(&mut buf)
+            .expect("We only create valid PSBT, serialization cannot fail");
+
+        Ok((buf, tx.txid()))
+    }
+
     /// Get the BIP174-serialized (inner) transaction.
     fn as_psbt_serialized(&self) -> Vec<u8> {
-        let mut buff = Vec::with_capacity(256);
+        let mut buff = Vec::with_capacity(self.psbt_serialized_len());
         self.psbt()
             .consensus_encode(&mut buff)
             .expect("We only create valid PSBT, serialization cannot fail");
         buff
     }
 
+    fn as_psbt_v2_serialized(&self) -> Vec<u8> {
+        utils::psbt_v2_serialized(self.psbt())
+    }
+
+    fn psbt_serialized_len(&self) -> usize {
+        utils::psbt_serialized_len(self.psbt())
+    }
+
     /// Get the BIP174-serialized (inner) transaction encoded in base64.
     fn as_psbt_string(&self) -> String {
         base64::encode(self.as_psbt_serialized())
     }
 
+    fn to_watchonly_psbt(
+        &self,
+        descriptors: &[&Descriptor<DerivedPublicKey>],
+        fingerprints: &BTreeMap<bip32::Fingerprint, bip32::KeySource>,
+    ) -> Result<String, Error> {
+        let psbt = utils::watchonly_psbt(self.psbt(), descriptors, fingerprints)
+            .map_err(TransactionSerialisationError::from)?;
+        Ok(base64::encode(encode::serialize(&psbt)))
+    }
+
     /// Create a RevaultTransaction from a base64-encoded BIP174-serialized transaction.
     fn from_psbt_str(psbt_str: &str) -> Result<Self, TransactionSerialisationError> {
         Self::from_psbt_serialized(&base64::decode(&psbt_str)?)
@@ -474,58 +1092,282 @@ impl<T: inner_mut::PrivateInnerMut + fmt::Debug + Clone + PartialEq> RevaultTran
         utils::psbt_fees(self.psbt()).expect("Fee computation bug: overflow")
     }
 
-    /// Get the inner unsigned transaction id
-    fn txid(&self) -> Txid {
-        self.psbt().global.unsigned_tx.txid()
+    fn fee_overpayment(&self) -> Result<u64, Error> {
+        if !self.is_finalized() {
+            return Err(Error::NotFinalized);
+        }
+
+        // The weight used when computing the fees at creation time
+        let estimated_weight: u64 = self
+            .tx()
+            .get_weight()
+            .try_into()
+            .expect("Can't be >u64::MAX");
+        let estimated_weight = utils::estimated_input_weights(self.psbt())
+            .map_err(TransactionSerialisationError::from)?
+            .into_iter()
+            .fold(estimated_weight, |sum, weight| {
+                sum.checked_add(weight).expect("Weight computation bug")
+            });
+        let actual_weight: u64 = self
+            .psbt()
+            .clone()
+            .extract_tx()
+            .get_weight()
+            .try_into()
+            .expect("Can't be >u64::MAX");
+
+        // Don't compute the feerate beforehand, the division would round it down. This can't
+        // overflow as a u128, and the result is at most the fees.
+        let overestimation = estimated_weight.saturating_sub(actual_weight);
+        let overpayment = (self.fees().as_sat() as u128)
+            .checked_mul(overestimation as u128)
+            .expect("Can't overflow a u128")
+            / estimated_weight as u128;
+        Ok(overpayment.try_into().expect("At most the fees"))
     }
 
-    /// Get the inner unsigned transaction hash with witness data
-    fn wtxid(&self) -> Wtxid {
-        self.psbt().global.unsigned_tx.wtxid()
+    fn meets_min_relay_feerate(&self, min_relay_feerate: u64) -> bool {
+        // The feerate is rounded down, which is exact for comparing against an integer
+        self.fee_summary().feerate_vb >= min_relay_feerate
     }
 
-    /// Get a reference to the inner transaction
-    fn tx(&self) -> &Transaction {
-        &self.psbt().global.unsigned_tx
+    fn meets_rbf_replacement(&self, replaced: &[&Self], incremental_relay_feerate: u64) -> bool {
+        let fee_summary = self.fee_summary();
+        replaced
+            .iter()
+            .try_fold(0u64, |sum, tx| sum.checked_add(tx.fees().as_sat()))
+            .and_then(|replaced_fees| {
+                incremental_relay_feerate
+                    .checked_mul(fee_summary.vsize)?
+                    .checked_add(replaced_fees)
+            })
+            .map(|required_fees| fee_summary.absolute_fee.as_sat() >= required_fees)
+            .unwrap_or(false)
     }
 
-    /// Extract the inner transaction of the inner PSBT. You likely want to be sure
-    /// the transaction [RevaultTransaction.is_finalized] before serializing it.
-    ///
-    /// The BIP174 Transaction Extractor (without any check, which are done in
-    /// [RevaultTransaction.finalize]).
-    fn into_tx(self) -> Transaction {
-        self.into_psbt().extract_tx()
+    fn fee_summary(&self) -> FeeSummary {
+        let psbt = self.psbt();
+        // We always set witness_utxo, it can only be a bug we introduced with amounts.
+        let total_in = psbt
+            .inputs
+            .iter()
+            .map(|psbtin| {
+                psbtin
+                    .witness_utxo
+                    .as_ref()
+                    .expect("We always set witness_utxo")
+                    .value
+            })
+            .fold(Amount::from_sat(0), |sum, value| {
+                sum.checked_add(Amount::from_sat(value))
+                    .expect("Fee computation bug: overflow")
+            });
+        let total_out =
+            psbt.global
+                .unsigned_tx
+                .output
+                .iter()
+                .fold(Amount::from_sat(0), |sum, txo| {
+                    sum.checked_add(Amount::from_sat(txo.value))
+                        .expect("Fee computation bug: overflow")
+                });
+        let absolute_fee = self.fees();
+        let vsize = utils::max_weight(psbt)
+            .checked_add(3)
+            .expect("Weight computation bug")
+            / 4;
+
+        FeeSummary {
+            total_in,
+            total_out,
+            absolute_fee,
+            vsize,
+            feerate_vb: absolute_fee.as_sat() / vsize,
+        }
     }
-}
 
-/// A transaction that can be CPFPed
-pub trait CpfpableTransaction: RevaultTransaction {
-    /// Return the txin refering to the output to spend to CPFP this transaction, if any.
-    fn cpfp_txin(
-        &self,
-        cpfp_descriptor: &CpfpDescriptor,
-        secp: &secp256k1::Secp256k1<impl secp256k1::Verification>,
-    ) -> Option<CpfpTxIn> {
-        let psbtouts = &self.psbt().outputs;
+    fn input_weights(&self) -> Vec<u64> {
+        utils::input_weights(self.psbt())
+    }
 
-        for i in 0..psbtouts.len() {
-            // For instance the external outputs of the Spend have no bip32_derivation
-            if psbtouts[i].bip32_derivation.is_empty() {
-                continue;
-            }
+    fn signals_rbf(&self) -> bool {
+        self.tx()
+            .input
+            .iter()
+            .any(|txin| txin.sequence < u32::MAX - 1)
+    }
 
-            // But when we do set it, the path always have a depth of 1
-            let der_path = &psbtouts[i].bip32_derivation.values().next().unwrap().1;
-            assert_eq!(der_path.len(), 1,);
-            let der_index = der_path[0];
-            let der_cpfp_desc = cpfp_descriptor.derive(der_index, secp);
+    fn is_spendable_at(&self, input_confirmation_heights: &[u32], current_height: u32) -> bool {
+        let txins = &self.tx().input;
+        if txins.len() != input_confirmation_heights.len() {
+            return false;
+        }
 
-            // Is it the CPFP txo?
-            if self.tx().output[i].script_pubkey == der_cpfp_desc.inner().script_pubkey() {
-                let txo = &self.tx().output[i];
-                let prev_txout = CpfpTxOut::new(Amount::from_sat(txo.value), &der_cpfp_desc);
-                return Some(CpfpTxIn::new(
+        txins
+            .iter()
+            .zip(input_confirmation_heights.iter())
+            .all(|(txin, conf_height)| {
+                sequence_csv(txin.sequence)
+                    .and_then(|csv| conf_height.checked_add(csv))
+                    .map(|mature_height| current_height >= mature_height)
+                    .unwrap_or(false)
+            })
+    }
+
+    fn has_nonstandard_output(&self) -> bool {
+        self.check_outputs_standard().is_err()
+    }
+
+    fn check_outputs_standard(&self) -> Result<(), PsbtValidationError> {
+        match self
+            .tx()
+            .output
+            .iter()
+            .position(|txo| !utils::is_standard_script_pubkey(&txo.script_pubkey))
+        {
+            Some(index) => Err(PsbtValidationError::NonStandardOutput(index)),
+            None => Ok(()),
+        }
+    }
+
+    fn check_standardness(&self) -> Result<(), Vec<StandardnessViolation>> {
+        let mut violations = Vec::new();
+
+        for (index, txo) in self.tx().output.iter().enumerate() {
+            if txo.value < txo.script_pubkey.dust_value().as_sat() {
+                violations.push(StandardnessViolation::DustOutput(index));
+            }
+            if !utils::is_standard_script_pubkey(&txo.script_pubkey) {
+                violations.push(StandardnessViolation::NonStandardOutput(index));
+            }
+        }
+
+        let weight = utils::max_weight(self.psbt());
+        if weight > MAX_STANDARD_TX_WEIGHT as u64 {
+            violations.push(StandardnessViolation::TooLarge(weight));
+        }
+
+        let feerate = self.fee_summary().feerate_vb;
+        if feerate < MIN_RELAY_FEERATE {
+            violations.push(StandardnessViolation::FeerateTooLow(feerate));
+        }
+
+        if !self.signals_rbf() {
+            violations.push(StandardnessViolation::NoRbfSignaling);
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    fn num_inputs(&self) -> usize {
+        self.tx().input.len()
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.tx().output.len()
+    }
+
+    fn output_witness_programs(&self) -> Vec<Option<(u8, Vec<u8>)>> {
+        self.tx()
+            .output
+            .iter()
+            .map(|txout| utils::witness_program(&txout.script_pubkey))
+            .collect()
+    }
+
+    fn output_addresses(&self, network: Network) -> Vec<Option<Address>> {
+        self.tx()
+            .output
+            .iter()
+            .map(|txout| Address::from_script(&txout.script_pubkey, network))
+            .collect()
+    }
+
+    fn same_outputs_as(&self, other: &Self) -> bool {
+        let spks = |tx: &Transaction| -> HashSet<Script> {
+            tx.output
+                .iter()
+                .map(|txo| txo.script_pubkey.clone())
+                .collect()
+        };
+        spks(self.tx()) == spks(other.tx())
+    }
+
+    fn conflicts_with(&self, other: &Self) -> bool {
+        if self.txid() == other.txid() {
+            return false;
+        }
+
+        let spent: HashSet<&OutPoint> = self
+            .tx()
+            .input
+            .iter()
+            .map(|txin| &txin.previous_output)
+            .collect();
+        other
+            .tx()
+            .input
+            .iter()
+            .any(|txin| spent.contains(&txin.previous_output))
+    }
+
+    /// Get the inner unsigned transaction id
+    fn txid(&self) -> Txid {
+        self.psbt().global.unsigned_tx.txid()
+    }
+
+    /// Get the inner unsigned transaction hash with witness data
+    fn wtxid(&self) -> Wtxid {
+        self.psbt().global.unsigned_tx.wtxid()
+    }
+
+    /// Get a reference to the inner transaction
+    fn tx(&self) -> &Transaction {
+        &self.psbt().global.unsigned_tx
+    }
+
+    /// Extract the inner transaction of the inner PSBT. You likely want to be sure
+    /// the transaction [RevaultTransaction.is_finalized] before serializing it.
+    ///
+    /// The BIP174 Transaction Extractor (without any check, which are done in
+    /// [RevaultTransaction.finalize]).
+    fn into_tx(self) -> Transaction {
+        self.into_psbt().extract_tx()
+    }
+}
+
+/// A transaction that can be CPFPed
+pub trait CpfpableTransaction: RevaultTransaction {
+    /// Return the txin refering to the output to spend to CPFP this transaction, if any.
+    fn cpfp_txin(
+        &self,
+        cpfp_descriptor: &CpfpDescriptor,
+        secp: &secp256k1::Secp256k1<impl secp256k1::Verification>,
+    ) -> Option<CpfpTxIn> {
+        let psbtouts = &self.psbt().outputs;
+
+        for i in 0..psbtouts.len() {
+            // For instance the external outputs of the Spend have no bip32_derivation
+            if psbtouts[i].bip32_derivation.is_empty() {
+                continue;
+            }
+
+            // But when we do set it, the path always have a depth of 1
+            let der_path = &psbtouts[i].bip32_derivation.values().next().unwrap().1;
+            assert_eq!(der_path.len(), 1,);
+            let der_index = der_path[0];
+            let der_cpfp_desc = cpfp_descriptor.derive(der_index, secp);
+
+            // Is it the CPFP txo?
+            if self.tx().output[i].script_pubkey == der_cpfp_desc.inner().script_pubkey() {
+                let txo = &self.tx().output[i];
+                let prev_txout = CpfpTxOut::new(Amount::from_sat(txo.value), &der_cpfp_desc);
+                return Some(CpfpTxIn::new(
                     OutPoint {
                         txid: self.txid(),
                         vout: i.try_into().expect("vout doesn't fit in a u32?"),
@@ -551,10 +1393,80 @@ pub trait CpfpableTransaction: RevaultTransaction {
             .expect("Weight is never 0")
     }
 
+    /// Get the feerate this transaction would have, as per [CpfpableTransaction::max_feerate], if
+    /// the input at `input_index` was removed along with its value. Useful to evaluate the
+    /// impact of each input when trimming a batch.
+    ///
+    /// Will error with [TransactionCreationError::NegativeFees] if the remaining inputs don't
+    /// cover the outputs.
+    fn feerate_without_input(&self, input_index: usize) -> Result<u64, Error> {
+        let mut psbt = self.psbt().clone();
+        if input_index >= psbt.inputs.len() {
+            return Err(InputSatisfactionError::OutOfBounds.into());
+        }
+        psbt.inputs.remove(input_index);
+        psbt.global.unsigned_tx.input.remove(input_index);
+
+        let fees = utils::psbt_fees(&psbt)
+            .ok_or(TransactionCreationError::NegativeFees)?
+            .as_sat();
+        let weight = utils::max_weight(&psbt);
+        Ok(fees
+            .checked_add(weight - 1) // Weight is never 0
+            .expect("Feerate computation bug, fees >u64::MAX")
+            .checked_div(weight)
+            .expect("Weight is never 0"))
+    }
+
     /// Get the size of this transaction, assuming fully-satisfied inputs. If the transaction
     /// is already finalized, returns the exact size in witness units. Otherwise computes the
     /// maximum reasonable weight of a satisfaction.
     fn max_weight(&self) -> u64;
+
+    /// Get the combined weight of this transaction and the `child` CPFP transaction bumping its
+    /// fees, assuming fully-satisfied inputs (see [CpfpableTransaction::max_weight]).
+    fn package_weight(&self, child: &CpfpTransaction) -> u64 {
+        self.max_weight()
+            .checked_add(utils::max_weight(child.psbt()))
+            .expect("Weight computation bug: overflow")
+    }
+
+    /// Get the package of this transaction and the `child` CPFP transaction bumping its fees, as
+    /// hex-encoded transactions in the order expected by bitcoind's `submitpackage` (parent
+    /// first).
+    ///
+    /// ## Errors
+    /// - If this transaction or the `child` is not finalized
+    /// - If the `child` doesn't spend the CPFP output (of this `cpfp_descriptor`) of this
+    ///   transaction
+    fn cpfp_package(
+        &self,
+        child: &CpfpTransaction,
+        cpfp_descriptor: &CpfpDescriptor,
+        secp: &secp256k1::Secp256k1<impl secp256k1::Verification>,
+    ) -> Result<Vec<String>, Error> {
+        if !self.is_finalized() || !child.is_finalized() {
+            return Err(Error::NotFinalized);
+        }
+
+        let cpfp_outpoint = self
+            .cpfp_txin(cpfp_descriptor, secp)
+            .ok_or(Error::InvalidCpfpChild)?
+            .outpoint();
+        if !child
+            .tx()
+            .input
+            .iter()
+            .any(|txin| txin.previous_output == cpfp_outpoint)
+        {
+            return Err(Error::InvalidCpfpChild);
+        }
+
+        Ok(vec![
+            encode::serialize_hex(&self.clone().into_psbt().extract_tx()),
+            encode::serialize_hex(&child.psbt().clone().extract_tx()),
+        ])
+    }
 }
 
 /// The funding transaction, we don't create nor sign it.
@@ -704,6 +1616,369 @@ impl CancelTransactionsBatch {
     }
 }
 
+/// A store of pre-signed Cancel transactions, indexed by the Unvault output they spend and the
+/// feerate (in sats/vbyte) they were created with.
+#[derive(Debug, Clone, Default)]
+pub struct CancelStore {
+    cancels: HashMap<OutPoint, BTreeMap<u64, CancelTransaction>>,
+}
+
+impl CancelStore {
+    /// Create an empty store
+    pub fn new() -> CancelStore {
+        CancelStore::default()
+    }
+
+    /// Store a Cancel transaction created with this `feerate`, in sats/vbyte. Returns the Cancel
+    /// previously stored for this Unvault output and feerate, if any.
+    pub fn insert(&mut self, cancel: CancelTransaction, feerate: u64) -> Option<CancelTransaction> {
+        // A Cancel always has a single input, the Unvault one.
+        let unvault_outpoint = cancel.tx().input[0].previous_output;
+        self.cancels
+            .entry(unvault_outpoint)
+            .or_default()
+            .insert(feerate, cancel)
+    }
+
+    /// Store all the Cancel transactions of a batch.
+    pub fn insert_batch(&mut self, batch: CancelTransactionsBatch) {
+        for (feerate, cancel) in batch.feerates_map() {
+            self.insert(cancel, feerate.as_sat());
+        }
+    }
+
+    /// Get the Cancel transaction spending this Unvault output with the lowest feerate that is
+    /// at least `target` sats/vbyte.
+    pub fn best_cancel_for_feerate(
+        &self,
+        unvault_outpoint: &OutPoint,
+        target: u64,
+    ) -> Option<&CancelTransaction> {
+        self.cancels
+            .get(unvault_outpoint)?
+            .range(target..)
+            .next()
+            .map(|(_, cancel)| cancel)
+    }
+}
+
+/// The entire chain of pre-signed transactions for a deposit.
+#[derive(Debug, Clone)]
+pub struct RevaultTransactionChain {
+    unvault: UnvaultTransaction,
+    cancel_batch: CancelTransactionsBatch,
+    emergency: EmergencyTransaction,
+    unvault_emergency: UnvaultEmergencyTransaction,
+}
+
+impl RevaultTransactionChain {
+    /// Create a chain out of its transactions, for instance after parsing them.
+    ///
+    /// Will error with [Error::MismatchedEmergencyAddress] if the Emergency and Unvault Emergency
+    /// transactions don't pay to the same Emergency Script.
+    pub fn new(
+        unvault: UnvaultTransaction,
+        cancel_batch: CancelTransactionsBatch,
+        emergency: EmergencyTransaction,
+        unvault_emergency: UnvaultEmergencyTransaction,
+    ) -> Result<RevaultTransactionChain, Error> {
+        // They both have a single output, the Emergency one.
+        if emergency.tx().output[0].script_pubkey != unvault_emergency.tx().output[0].script_pubkey
+        {
+            return Err(Error::MismatchedEmergencyAddress);
+        }
+
+        Ok(RevaultTransactionChain {
+            unvault,
+            cancel_batch,
+            emergency,
+            unvault_emergency,
+        })
+    }
+
+    /// Create the entire chain of pre-signed transactions for this derivation index out of a
+    /// deposit. Same as [transaction_chain], as a [RevaultTransactionChain].
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_descriptors<C: secp256k1::Verification>(
+        deposit_outpoint: OutPoint,
+        deposit_amount: Amount,
+        deposit_descriptor: &DepositDescriptor,
+        unvault_descriptor: &UnvaultDescriptor,
+        cpfp_descriptor: &CpfpDescriptor,
+        derivation_index: ChildNumber,
+        emer_address: EmergencyAddress,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<RevaultTransactionChain, Error> {
+        let (unvault, cancel_batch, emergency, unvault_emergency) = transaction_chain(
+            deposit_outpoint,
+            deposit_amount,
+            deposit_descriptor,
+            unvault_descriptor,
+            cpfp_descriptor,
+            derivation_index,
+            emer_address,
+            secp,
+        )?;
+
+        Ok(RevaultTransactionChain {
+            unvault,
+            cancel_batch,
+            emergency,
+            unvault_emergency,
+        })
+    }
+
+    /// Get a reference to the Unvault transaction
+    pub fn unvault(&self) -> &UnvaultTransaction {
+        &self.unvault
+    }
+
+    /// Move out the Unvault transaction
+    pub fn into_unvault(self) -> UnvaultTransaction {
+        self.unvault
+    }
+
+    /// Get a reference to the batch of Cancel transactions
+    pub fn cancel_batch(&self) -> &CancelTransactionsBatch {
+        &self.cancel_batch
+    }
+
+    /// Move out the batch of Cancel transactions
+    pub fn into_cancel_batch(self) -> CancelTransactionsBatch {
+        self.cancel_batch
+    }
+
+    /// Get a reference to the Emergency transaction
+    pub fn emergency(&self) -> &EmergencyTransaction {
+        &self.emergency
+    }
+
+    /// Move out the Emergency transaction
+    pub fn into_emergency(self) -> EmergencyTransaction {
+        self.emergency
+    }
+
+    /// Get a reference to the Unvault Emergency transaction
+    pub fn unvault_emergency(&self) -> &UnvaultEmergencyTransaction {
+        &self.unvault_emergency
+    }
+
+    /// Move out the Unvault Emergency transaction
+    pub fn into_unvault_emergency(self) -> UnvaultEmergencyTransaction {
+        self.unvault_emergency
+    }
+
+    /// Check the revocation transactions of the chain don't have a higher lock_time than the
+    /// Unvault, as they must be broadcastable whenever the Unvault is. A non-zero lock_time
+    /// expressed in another unit (block height or timestamp) than the Unvault's can't be
+    /// compared to it and is rejected as well.
+    ///
+    /// Errors with the txid and lock_time of the first offending transaction.
+    pub fn lock_times_consistent(&self) -> Result<(), PsbtValidationError> {
+        let unvault_lock_time = self.unvault.tx().lock_time;
+        let unvault_is_timestamp = unvault_lock_time >= LOCKTIME_THRESHOLD;
+        for psbt in self.iter().skip(1) {
+            let tx = &psbt.global.unsigned_tx;
+            let mixed_units =
+                tx.lock_time != 0 && (tx.lock_time >= LOCKTIME_THRESHOLD) != unvault_is_timestamp;
+            if mixed_units || tx.lock_time > unvault_lock_time {
+                return Err(PsbtValidationError::InconsistentLockTime(
+                    tx.txid(),
+                    tx.lock_time,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the value of the deposit this chain protects, as spent by the Unvault transaction.
+    pub fn vault_value(&self) -> Amount {
+        // The Unvault has a single input, and we always set its witness_utxo.
+        let deposit_utxo = self.unvault.psbt().inputs[0]
+            .witness_utxo
+            .as_ref()
+            .expect("We always set witness_utxo");
+        Amount::from_sat(deposit_utxo.value)
+    }
+
+    /// Check all the transactions of this (parsed) chain use the Scripts derived from these
+    /// descriptors at `derivation_index`: the Unvault spends the deposit and pays to the Unvault
+    /// and CPFP descriptors, the Cancels revault its output to the deposit descriptor and both
+    /// Emergency transactions pay to `emer_address`. Always check this before signing a chain you
+    /// did not create!
+    ///
+    /// Errors with the txid of the first offending transaction.
+    pub fn validate<C: secp256k1::Verification>(
+        &self,
+        deposit_descriptor: &DepositDescriptor,
+        unvault_descriptor: &UnvaultDescriptor,
+        cpfp_descriptor: &CpfpDescriptor,
+        derivation_index: ChildNumber,
+        emer_address: &EmergencyAddress,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<(), PsbtValidationError> {
+        let (der_deposit_descriptor, der_unvault_descriptor, der_cpfp_descriptor) = (
+            deposit_descriptor.derive(derivation_index, secp),
+            unvault_descriptor.derive(derivation_index, secp),
+            cpfp_descriptor.derive(derivation_index, secp),
+        );
+        let deposit_spk = der_deposit_descriptor.inner().script_pubkey();
+        let emer_spk = emer_address.address().script_pubkey();
+        // All our transactions have a single input, and we always set its witness_utxo
+        let spent_spk = |psbt: &Psbt| -> Script {
+            psbt.inputs[0]
+                .witness_utxo
+                .as_ref()
+                .expect("We always set witness_utxo")
+                .script_pubkey
+                .clone()
+        };
+        let invalid = |tx: &Transaction| PsbtValidationError::InvalidChainTransaction(tx.txid());
+
+        let unvault = &self.unvault;
+        if spent_spk(unvault.psbt()) != deposit_spk
+            || unvault.check_cpfp_txout(&der_cpfp_descriptor).is_err()
+        {
+            return Err(invalid(unvault.tx()));
+        }
+        let unvault_outpoint = unvault
+            .unvault_outpoint(&der_unvault_descriptor)
+            .ok_or_else(|| invalid(unvault.tx()))?;
+
+        let cancels = &self.cancel_batch;
+        for cancel in [
+            cancels.feerate_20(),
+            cancels.feerate_100(),
+            cancels.feerate_200(),
+            cancels.feerate_500(),
+            cancels.feerate_1000(),
+        ]
+        .iter()
+        {
+            if cancel.tx().input[0].previous_output != unvault_outpoint
+                || !cancel.revaults_to(&der_deposit_descriptor)
+            {
+                return Err(invalid(cancel.tx()));
+            }
+        }
+
+        let emergency = &self.emergency;
+        if spent_spk(emergency.psbt()) != deposit_spk
+            || emergency.tx().input[0].previous_output != unvault.tx().input[0].previous_output
+            || emergency.tx().output[0].script_pubkey != emer_spk
+        {
+            return Err(invalid(emergency.tx()));
+        }
+
+        let unvault_emergency = &self.unvault_emergency;
+        if unvault_emergency.tx().input[0].previous_output != unvault_outpoint
+            || unvault_emergency.tx().output[0].script_pubkey != emer_spk
+        {
+            return Err(invalid(unvault_emergency.tx()));
+        }
+
+        Ok(())
+    }
+
+    /// Serialize the chain as a compact binary bundle: the length-prefixed concatenation of the
+    /// serialized PSBTs in the order of [RevaultTransactionChain::iter], itself length-prefixed.
+    pub fn serialize(&self) -> Vec<u8> {
+        let psbts: Vec<Vec<u8>> = self.iter().map(encode::serialize).collect();
+        encode::serialize(&psbts)
+    }
+
+    /// Parse a chain out of a bundle created with [RevaultTransactionChain::serialize]. Each
+    /// transaction goes through the same checks as when parsed from a PSBT alone.
+    pub fn deserialize(raw_chain: &[u8]) -> Result<RevaultTransactionChain, Error> {
+        let psbts: Vec<Vec<u8>> =
+            encode::deserialize(raw_chain).map_err(TransactionSerialisationError::from)?;
+        if psbts.len() != 8 {
+            return Err(TransactionSerialisationError::Encode(format!(
+                "Invalid number of transactions in chain: '{}'",
+                psbts.len()
+            ))
+            .into());
+        }
+
+        let cancel_batch = CancelTransactionsBatch {
+            feerate_20: CancelTransaction::from_raw_psbt(&psbts[1])?,
+            feerate_100: CancelTransaction::from_raw_psbt(&psbts[2])?,
+            feerate_200: CancelTransaction::from_raw_psbt(&psbts[3])?,
+            feerate_500: CancelTransaction::from_raw_psbt(&psbts[4])?,
+            feerate_1000: CancelTransaction::from_raw_psbt(&psbts[5])?,
+        };
+        RevaultTransactionChain::new(
+            UnvaultTransaction::from_raw_psbt(&psbts[0])?,
+            cancel_batch,
+            EmergencyTransaction::from_raw_psbt(&psbts[6])?,
+            UnvaultEmergencyTransaction::from_raw_psbt(&psbts[7])?,
+        )
+    }
+
+    /// Iterate over the PSBTs of all the transactions of the chain: the Unvault, the Cancels by
+    /// ascending feerate, the Emergency and the Unvault Emergency.
+    ///
+    /// NOTE: [RevaultTransaction] is not object-safe, hence we expose the inner PSBTs.
+    pub fn iter(&self) -> impl Iterator<Item = &Psbt> {
+        let cancels = &self.cancel_batch;
+        vec![
+            self.unvault.psbt(),
+            cancels.feerate_20().psbt(),
+            cancels.feerate_100().psbt(),
+            cancels.feerate_200().psbt(),
+            cancels.feerate_500().psbt(),
+            cancels.feerate_1000().psbt(),
+            self.emergency.psbt(),
+            self.unvault_emergency.psbt(),
+        ]
+        .into_iter()
+    }
+}
+
+impl IntoIterator for RevaultTransactionChain {
+    type Item = Psbt;
+    type IntoIter = std::vec::IntoIter<Psbt>;
+
+    /// Move out the PSBTs of all the transactions of the chain, in the same order as
+    /// [RevaultTransactionChain::iter].
+    fn into_iter(self) -> Self::IntoIter {
+        let [cancel_20, cancel_100, cancel_200, cancel_500, cancel_1000] =
+            self.cancel_batch.all_feerates();
+        vec![
+            self.unvault.into_psbt(),
+            cancel_20.into_psbt(),
+            cancel_100.into_psbt(),
+            cancel_200.into_psbt(),
+            cancel_500.into_psbt(),
+            cancel_1000.into_psbt(),
+            self.emergency.into_psbt(),
+            self.unvault_emergency.into_psbt(),
+        ]
+        .into_iter()
+    }
+}
+
+/// Get the value of the Unvault output that would be created out of a deposit of this value,
+/// without the deposit outpoint. Errors if the deposit is too small to be unvaulted.
+pub fn deposit_to_unvault_value(
+    deposit_value: Amount,
+    der_deposit_descriptor: &DerivedDepositDescriptor,
+    der_unvault_descriptor: &DerivedUnvaultDescriptor,
+    der_cpfp_descriptor: &DerivedCpfpDescriptor,
+) -> Result<Amount, TransactionCreationError> {
+    // The outpoint doesn't affect the Unvault value.
+    let deposit_txin = DepositTxIn::new(
+        OutPoint::default(),
+        DepositTxOut::new(deposit_value, der_deposit_descriptor),
+    );
+    let unvault_tx =
+        UnvaultTransaction::new(deposit_txin, der_unvault_descriptor, der_cpfp_descriptor)?;
+
+    // The Unvault output is always the first one.
+    Ok(Amount::from_sat(unvault_tx.tx().output[0].value))
+}
+
 /// Get the chain of pre-signed transactions out of a deposit available for a manager.
 #[allow(clippy::too_many_arguments)]
 pub fn transaction_chain_manager<C: secp256k1::Verification>(
@@ -778,6 +2053,35 @@ pub fn transaction_chain<C: secp256k1::Verification>(
     Ok((unvault_tx, cancel_batch, emergency_tx, unvault_emergency_tx))
 }
 
+/// Get the total fees, in sats, needed to broadcast all these Emergency transactions at this
+/// `feerate` (in sat/vbyte). This is the reserve needed to bump them all in case of an
+/// emergency. Errors if the `feerate` is so high the total fees would overflow.
+pub fn total_emergency_cost(
+    emergency_txs: &[EmergencyTransaction],
+    feerate: u64,
+) -> Result<u64, TransactionCreationError> {
+    emergency_txs
+        .iter()
+        .try_fold(0u64, |total, tx| {
+            tx.max_vsize()
+                .checked_mul(feerate)
+                .and_then(|fees| total.checked_add(fees))
+        })
+        .ok_or(TransactionCreationError::FeerateTooHigh)
+}
+
+/// Get the total value, in sats, these Emergency transactions would send to the Emergency
+/// address if they were all broadcast.
+pub fn total_emergency_value(
+    emergency_txs: &[EmergencyTransaction],
+) -> Result<u64, TransactionCreationError> {
+    emergency_txs
+        .iter()
+        .flat_map(|tx| tx.tx().output.iter())
+        .try_fold(0u64, |total, txo| total.checked_add(txo.value))
+        .ok_or(TransactionCreationError::InsaneAmounts)
+}
+
 /// Get a spend transaction out of a list of deposits and derivation indexes.
 /// The derivation index used for the Spend CPFP is the highest of the deposits one.
 #[allow(clippy::too_many_arguments)]
@@ -792,8 +2096,37 @@ pub fn spend_tx_from_deposits<C: secp256k1::Verification>(
     check_insane_fees: bool,
     secp: &secp256k1::Secp256k1<C>,
 ) -> Result<SpendTransaction, TransactionCreationError> {
-    let mut max_deriv_index = ChildNumber::from(0);
-    let unvault_txins = deposit_txins
+    spend_tx_from_deposits_with_cpfp(
+        deposit_txins,
+        spend_txos,
+        change_txo,
+        deposit_descriptor,
+        unvault_descriptor,
+        cpfp_descriptor,
+        cpfp_descriptor,
+        lock_time,
+        check_insane_fees,
+        secp,
+    )
+}
+
+/// Same as [spend_tx_from_deposits], but the Spend CPFP output pays to the
+/// `spend_cpfp_descriptor` instead of the `cpfp_descriptor` of the Unvault transactions' CPFP
+/// outputs. Allows the Spend to be fee-bumped by a different set of keys.
+#[allow(clippy::too_many_arguments)]
+pub fn spend_tx_from_deposits_with_cpfp<C: secp256k1::Verification>(
+    deposit_txins: Vec<(OutPoint, Amount, ChildNumber)>,
+    spend_txos: Vec<SpendTxOut>,
+    change_txo: Option<DepositTxOut>,
+    deposit_descriptor: &DepositDescriptor,
+    unvault_descriptor: &UnvaultDescriptor,
+    cpfp_descriptor: &CpfpDescriptor,
+    spend_cpfp_descriptor: &CpfpDescriptor,
+    lock_time: u32,
+    check_insane_fees: bool,
+    secp: &secp256k1::Secp256k1<C>,
+) -> Result<SpendTransaction, TransactionCreationError> {
+    let unvault_txs = deposit_txins
         .into_iter()
         .map(|(outpoint, amount, deriv_index)| {
             let der_deposit_desc = deposit_descriptor.derive(deriv_index, secp);
@@ -801,12 +2134,197 @@ pub fn spend_tx_from_deposits<C: secp256k1::Verification>(
             let der_cpfp_desc = cpfp_descriptor.derive(deriv_index, secp);
 
             let txin = DepositTxIn::new(outpoint, DepositTxOut::new(amount, &der_deposit_desc));
-            if deriv_index > max_deriv_index {
-                max_deriv_index = deriv_index;
+            UnvaultTransaction::new(txin, &der_unvault_desc, &der_cpfp_desc)
+                .map(|unvault_tx| (unvault_tx, deriv_index))
+        })
+        .collect::<Result<Vec<(UnvaultTransaction, ChildNumber)>, TransactionCreationError>>()?;
+
+    spend_tx_from_unvaults(
+        &unvault_txs,
+        spend_txos,
+        change_txo,
+        unvault_descriptor,
+        spend_cpfp_descriptor,
+        lock_time,
+        check_insane_fees,
+        secp,
+    )
+}
+
+/// Get a spend transaction out of a list of deposits and derivation indexes, paying `amount`
+/// to a single `recipient` and the rest back to the `change_descriptor` after paying for fees
+/// at `feerate` (in sats/WU, on top of the Spend's CPFP output). The derivation index used for
+/// the Spend CPFP is the highest of the deposits one.
+///
+/// Will error with [TransactionCreationError::InsufficientFunds] if the deposits can't cover the
+/// amount and the fees, and with [TransactionCreationError::Dust] if the change would be dust.
+#[allow(clippy::too_many_arguments)]
+pub fn spend_to_single_recipient<C: secp256k1::Verification>(
+    deposit_txins: Vec<(OutPoint, Amount, ChildNumber)>,
+    recipient: &Address,
+    amount: Amount,
+    change_descriptor: &DerivedDepositDescriptor,
+    deposit_descriptor: &DepositDescriptor,
+    unvault_descriptor: &UnvaultDescriptor,
+    cpfp_descriptor: &CpfpDescriptor,
+    feerate: u64,
+    lock_time: u32,
+    secp: &secp256k1::Secp256k1<C>,
+) -> Result<SpendTransaction, TransactionCreationError> {
+    let recipient_txo = SpendTxOut::new(TxOut {
+        value: amount.as_sat(),
+        script_pubkey: recipient.script_pubkey(),
+    });
+
+    // First create the Spend with a minimal change to get its size. The change value is
+    // serialized on 8 bytes whichever it is, so the size won't change.
+    let dummy_change_value = change_descriptor.inner().script_pubkey().dust_value();
+    let dummy_spend_tx = spend_tx_from_deposits(
+        deposit_txins.clone(),
+        vec![recipient_txo.clone()],
+        Some(DepositTxOut::new(dummy_change_value, change_descriptor)),
+        deposit_descriptor,
+        unvault_descriptor,
+        cpfp_descriptor,
+        lock_time,
+        false,
+        secp,
+    )
+    .map_err(|e| match e {
+        TransactionCreationError::NegativeFees => TransactionCreationError::InsufficientFunds,
+        e => e,
+    })?;
+
+    // The CPFP output is always the first one.
+    let cpfp_value = dummy_spend_tx.tx().output[0].value;
+    let fees = feerate
+        .checked_mul(dummy_spend_tx.max_weight())
+        .ok_or(TransactionCreationError::InsaneFees)?;
+    let change_value = dummy_spend_tx
+        .fee_summary()
+        .total_in
+        .as_sat()
+        .checked_sub(amount.as_sat())
+        .and_then(|v| v.checked_sub(cpfp_value))
+        .and_then(|v| v.checked_sub(fees))
+        .ok_or(TransactionCreationError::InsufficientFunds)?;
+
+    spend_tx_from_deposits(
+        deposit_txins,
+        vec![recipient_txo],
+        Some(DepositTxOut::new(
+            Amount::from_sat(change_value),
+            change_descriptor,
+        )),
+        deposit_descriptor,
+        unvault_descriptor,
+        cpfp_descriptor,
+        lock_time,
+        true,
+        secp,
+    )
+}
+
+/// Get replacements of this unsigned `base_spend` paying fees at each of these `feerates` (in
+/// sats/WU), by decreasing the value of its output paying to the `change_descriptor`. The
+/// inputs and the other outputs are left untouched, so the replacements conflict with each other
+/// and can be signed in advance to be broadcast one after the other.
+///
+/// Will error with [TransactionCreationError::FeerateTooLow] if a replacement doesn't pay
+/// enough fees to replace the previous one (or the base Spend for the first one) as per
+/// [RevaultTransaction::meets_rbf_replacement], with [TransactionCreationError::InsufficientFunds]
+/// if the base Spend has no change output and with [TransactionCreationError::Dust] if the change
+/// left would be dust.
+pub fn spend_rbf_schedule(
+    base_spend: &SpendTransaction,
+    feerates: &[u64],
+    change_descriptor: &DerivedDepositDescriptor,
+) -> Result<Vec<SpendTransaction>, Error> {
+    if base_spend
+        .psbt()
+        .inputs
+        .iter()
+        .any(|psbtin| !psbtin.partial_sigs.is_empty() || psbtin.final_script_witness.is_some())
+    {
+        return Err(Error::AlreadySigned);
+    }
+
+    let change_spk = change_descriptor.inner().script_pubkey();
+    let change_index = base_spend
+        .tx()
+        .output
+        .iter()
+        .position(|txo| txo.script_pubkey == change_spk)
+        .ok_or(TransactionCreationError::InsufficientFunds)?;
+    let change_value = base_spend.tx().output[change_index].value;
+    // Only the value of the change output is modified, the weight stays the same.
+    let weight = base_spend.max_weight();
+    let base_fees = base_spend.fees().as_sat();
+
+    let mut schedule: Vec<SpendTransaction> = Vec::with_capacity(feerates.len());
+    for feerate in feerates {
+        let fees = feerate
+            .checked_mul(weight)
+            .ok_or(TransactionCreationError::InsaneFees)?;
+        if fees <= base_fees {
+            return Err(TransactionCreationError::FeerateTooLow.into());
+        }
+        if fees > INSANE_FEES {
+            return Err(TransactionCreationError::InsaneFees.into());
+        }
+        let new_change_value = change_value
+            .checked_sub(fees - base_fees)
+            .filter(|value| *value >= change_spk.dust_value().as_sat())
+            .ok_or(TransactionCreationError::Dust)?;
+
+        let mut spend_tx = base_spend.clone();
+        inner_mut::PrivateInnerMut::psbt_mut(&mut spend_tx)
+            .global
+            .unsigned_tx
+            .output[change_index]
+            .value = new_change_value;
+
+        // Each replacement must be able to replace the previous one
+        let replaced = schedule.last().unwrap_or(base_spend);
+        if !spend_tx.meets_rbf_replacement(&[replaced], INCREMENTAL_RELAY_FEERATE) {
+            return Err(TransactionCreationError::FeerateTooLow.into());
+        }
+        schedule.push(spend_tx);
+    }
+
+    Ok(schedule)
+}
+
+/// Get a spend transaction out of a list of already created Unvault transactions and their
+/// derivation indexes. The derivation index used for the Spend CPFP is the highest of the
+/// Unvaults one.
+///
+/// Will error if an Unvault transaction doesn't pay to the `unvault_descriptor` derived at its
+/// derivation index.
+#[allow(clippy::too_many_arguments)]
+pub fn spend_tx_from_unvaults<C: secp256k1::Verification>(
+    unvault_txs: &[(UnvaultTransaction, ChildNumber)],
+    spend_txos: Vec<SpendTxOut>,
+    change_txo: Option<DepositTxOut>,
+    unvault_descriptor: &UnvaultDescriptor,
+    cpfp_descriptor: &CpfpDescriptor,
+    lock_time: u32,
+    check_insane_fees: bool,
+    secp: &secp256k1::Secp256k1<C>,
+) -> Result<SpendTransaction, TransactionCreationError> {
+    let mut max_deriv_index = ChildNumber::from(0);
+    let unvault_txins = unvault_txs
+        .iter()
+        .map(|(unvault_tx, deriv_index)| {
+            if *deriv_index > max_deriv_index {
+                max_deriv_index = *deriv_index;
             }
 
-            UnvaultTransaction::new(txin, &der_unvault_desc, &der_cpfp_desc)
-                .map(|unvault_tx| unvault_tx.spend_unvault_txin(&der_unvault_desc))
+            let der_unvault_desc = unvault_descriptor.derive(*deriv_index, secp);
+            if unvault_tx.unvault_outpoint(&der_unvault_desc).is_none() {
+                return Err(TransactionCreationError::MissingUnvaultTxOut);
+            }
+            Ok(unvault_tx.spend_unvault_txin(&der_unvault_desc))
         })
         .collect::<Result<Vec<UnvaultTxIn>, TransactionCreationError>>()?;
 
@@ -824,14 +2342,35 @@ pub fn spend_tx_from_deposits<C: secp256k1::Verification>(
 #[cfg(any(test, feature = "fuzz"))]
 pub mod tests_helpers;
 
+#[cfg(any(test, feature = "fuzz", feature = "test-utils"))]
+pub mod test_utils;
+
 #[cfg(test)]
 mod tests {
-    use super::tests_helpers::derive_transactions;
-    use crate::{error::*, scripts::*};
-
-    use miniscript::bitcoin::{blockdata::constants::COIN_VALUE, secp256k1, OutPoint};
+    use super::{
+        spend_rbf_schedule, spend_to_single_recipient, spend_tx_from_deposits,
+        spend_tx_from_deposits_with_cpfp, spend_tx_from_unvaults,
+        test_utils::{test_vault, TestVault},
+        tests_helpers::{derive_transactions, from_raw_psbt_any_version, other_child_number},
+        utils, CancelTransaction, CpfpableTransaction, EmergencyTransaction, RevaultTransaction,
+        RevaultTransactionChain, UnvaultTransaction, INCREMENTAL_RELAY_FEERATE, INSANE_FEES,
+        LOCKTIME_THRESHOLD,
+    };
+    use crate::{error::*, scripts::*, txins::*, txouts::*};
+
+    use miniscript::{
+        bitcoin::{
+            blockdata::constants::COIN_VALUE,
+            consensus::encode,
+            secp256k1,
+            util::{bip32, psbt::PartiallySignedTransaction as Psbt},
+            Address, Amount, Network, OutPoint, SigHashType, TxOut,
+        },
+        descriptor::{DescriptorPublicKey, DescriptorXKey, Wildcard},
+        DescriptorTrait,
+    };
 
-    use std::str::FromStr;
+    use std::{collections::BTreeMap, str::FromStr};
 
     #[test]
     fn transaction_derivation() {
@@ -981,6 +2520,666 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn any_version_parsing() {
+        let vault = test_vault();
+        let deposit_outpoint = TestVault::default_outpoint();
+        let chain = vault
+            .chain(deposit_outpoint, Amount::from_sat(100_000_000))
+            .unwrap();
+        let cancel_tx = chain.cancel_batch().feerate_20();
+
+        // A version 1 transaction is only accepted by the test-only parser
+        let mut v1_psbt = cancel_tx.psbt().clone();
+        v1_psbt.global.unsigned_tx.version = 1;
+        let v1_psbt = encode::serialize(&v1_psbt);
+        assert!(matches!(
+            CancelTransaction::from_raw_psbt(&v1_psbt),
+            Err(TransactionSerialisationError::Validation(
+                PsbtValidationError::InvalidTransactionVersion(1)
+            ))
+        ));
+        let v1_cancel_tx: CancelTransaction = from_raw_psbt_any_version(&v1_psbt).unwrap();
+        assert_eq!(v1_cancel_tx.tx().version, 1);
+        assert_eq!(
+            &from_raw_psbt_any_version::<CancelTransaction>(&cancel_tx.as_psbt_serialized())
+                .unwrap(),
+            cancel_tx
+        );
+    }
+
+    #[test]
+    fn lock_times_units() {
+        let vault = test_vault();
+        let chain = vault
+            .chain(TestVault::default_outpoint(), Amount::from_sat(100_000_000))
+            .unwrap();
+
+        // An Unvault locked until a UNIX timestamp
+        let mut unvault_psbt = chain.unvault().psbt().clone();
+        unvault_psbt.global.unsigned_tx.lock_time = 1_600_000_000;
+        let unvault_tx =
+            UnvaultTransaction::from_raw_psbt(&encode::serialize(&unvault_psbt)).unwrap();
+        let with_emer_lock_time = |lock_time: u32| {
+            let mut emer_psbt = chain.emergency().psbt().clone();
+            emer_psbt.global.unsigned_tx.lock_time = lock_time;
+            let emer_txid = emer_psbt.global.unsigned_tx.txid();
+            let emer_tx =
+                EmergencyTransaction::from_raw_psbt(&encode::serialize(&emer_psbt)).unwrap();
+            let chain = RevaultTransactionChain::new(
+                unvault_tx.clone(),
+                chain.cancel_batch().clone(),
+                emer_tx,
+                chain.unvault_emergency().clone(),
+            )
+            .unwrap();
+            (chain, emer_txid)
+        };
+
+        // A lower timestamp, or no lock_time at all, is fine
+        let (locked_chain, _) = with_emer_lock_time(1_500_000_000);
+        assert_eq!(locked_chain.lock_times_consistent(), Ok(()));
+        let (locked_chain, _) = with_emer_lock_time(0);
+        assert_eq!(locked_chain.lock_times_consistent(), Ok(()));
+
+        // A block height is lower than the timestamp, but can't be compared to it
+        let (locked_chain, emer_txid) = with_emer_lock_time(1);
+        assert_eq!(
+            locked_chain.lock_times_consistent(),
+            Err(PsbtValidationError::InconsistentLockTime(emer_txid, 1))
+        );
+        let (locked_chain, emer_txid) = with_emer_lock_time(LOCKTIME_THRESHOLD - 1);
+        assert_eq!(
+            locked_chain.lock_times_consistent(),
+            Err(PsbtValidationError::InconsistentLockTime(
+                emer_txid,
+                LOCKTIME_THRESHOLD - 1
+            ))
+        );
+    }
+
+    #[test]
+    fn chain_validation() {
+        let vault = test_vault();
+        let secp = &vault.secp;
+        let deposit_outpoint = TestVault::default_outpoint();
+        let chain = vault
+            .chain(deposit_outpoint, Amount::from_sat(100_000_000))
+            .unwrap();
+
+        assert_eq!(
+            chain.validate(
+                &vault.deposit_descriptor,
+                &vault.unvault_descriptor,
+                &vault.cpfp_descriptor,
+                vault.child_number,
+                &vault.emergency_address,
+                secp
+            ),
+            Ok(())
+        );
+        // The first transaction not paying to the descriptors at this index is reported
+        let other_index = other_child_number(vault.child_number);
+        assert_eq!(
+            chain.validate(
+                &vault.deposit_descriptor,
+                &vault.unvault_descriptor,
+                &vault.cpfp_descriptor,
+                other_index,
+                &vault.emergency_address,
+                secp
+            ),
+            Err(PsbtValidationError::InvalidChainTransaction(
+                chain.unvault().txid()
+            ))
+        );
+        let other_emergency_address = EmergencyAddress::from(Address::p2wsh(
+            &vault
+                .deposit_descriptor
+                .derive(other_index, secp)
+                .inner()
+                .explicit_script(),
+            Network::Bitcoin,
+        ))
+        .expect("It's a P2WSH");
+        assert_eq!(
+            chain.validate(
+                &vault.deposit_descriptor,
+                &vault.unvault_descriptor,
+                &vault.cpfp_descriptor,
+                vault.child_number,
+                &other_emergency_address,
+                secp
+            ),
+            Err(PsbtValidationError::InvalidChainTransaction(
+                chain.emergency().txid()
+            ))
+        );
+    }
+
+    #[test]
+    fn same_outputs() {
+        let vault = test_vault();
+        let deposit_outpoint = TestVault::default_outpoint();
+        let chain = vault
+            .chain(deposit_outpoint, Amount::from_sat(100_000_000))
+            .unwrap();
+        let cancel_batch = chain.cancel_batch();
+
+        // All the Cancel transactions pay to the same deposit output, at various amounts
+        assert!(cancel_batch
+            .feerate_20()
+            .same_outputs_as(cancel_batch.feerate_1000()));
+        let other_cancel_tx = CancelTransaction::new(
+            chain
+                .unvault()
+                .revault_unvault_txin(&vault.der_unvault_descriptor()),
+            &vault
+                .deposit_descriptor
+                .derive(other_child_number(vault.child_number), &vault.secp),
+            Amount::from_sat(50),
+        )
+        .unwrap();
+        assert!(!cancel_batch.feerate_20().same_outputs_as(&other_cancel_tx));
+    }
+
+    #[test]
+    fn diagnose_insufficient_signatures() {
+        let vault = test_vault();
+        let deposit_outpoint = TestVault::default_outpoint();
+        let chain = vault
+            .chain(deposit_outpoint, Amount::from_sat(100_000_000))
+            .unwrap();
+        let mut cancel_tx = chain.cancel_batch().feerate_20().clone();
+        let sigs = vault.signatures(&cancel_tx, 0, &vault.stakeholders_priv);
+
+        // A single stakeholder signature satisfies none of the spending paths
+        cancel_tx
+            .add_signatures(0, vec![sigs[0]], &vault.secp)
+            .unwrap();
+        assert_eq!(
+            cancel_tx.diagnose_finalize(),
+            vec![(0, FinalizeDiagnosis::InsufficientSignatures(1, 3))]
+        );
+        cancel_tx
+            .add_signatures(0, sigs[1..].to_vec(), &vault.secp)
+            .unwrap();
+        assert!(cancel_tx.diagnose_finalize().is_empty());
+    }
+
+    #[test]
+    fn add_signatures_all_or_nothing() {
+        let vault = test_vault();
+        let deposit_outpoint = TestVault::default_outpoint();
+        let chain = vault
+            .chain(deposit_outpoint, Amount::from_sat(100_000_000))
+            .unwrap();
+        let unsigned_cancel_tx = chain.cancel_batch().feerate_20().clone();
+        let sigs = vault.signatures(&unsigned_cancel_tx, 0, &vault.stakeholders_priv);
+
+        // If any signature is invalid, none of them is added
+        let mut bad_sigs = sigs.clone();
+        bad_sigs.push((sigs[0].0, sigs[1].1));
+        let mut cancel_tx = unsigned_cancel_tx.clone();
+        assert!(matches!(
+            cancel_tx.add_signatures(0, bad_sigs, &vault.secp),
+            Err(InputSatisfactionError::InvalidSignature(_, pk, _)) if pk == sigs[0].0
+        ));
+        assert_eq!(cancel_tx, unsigned_cancel_tx);
+
+        cancel_tx.add_signatures(0, sigs, &vault.secp).unwrap();
+        assert_eq!(cancel_tx.psbt().inputs[0].partial_sigs.len(), 3);
+    }
+
+    #[test]
+    fn single_recipient_spend() {
+        let vault = test_vault();
+        let secp = &vault.secp;
+        let deposit_value = 1_000_000;
+        let deposits = vec![(
+            TestVault::default_outpoint(),
+            Amount::from_sat(deposit_value),
+            vault.child_number,
+        )];
+        let der_deposit_descriptor = vault.der_deposit_descriptor();
+        let recipient = &Address::p2wsh(
+            &vault
+                .deposit_descriptor
+                .derive(other_child_number(vault.child_number), secp)
+                .inner()
+                .explicit_script(),
+            Network::Bitcoin,
+        );
+
+        // Pay a single recipient, getting the change back to the deposit descriptor
+        let spend_tx = spend_to_single_recipient(
+            deposits.clone(),
+            recipient,
+            Amount::from_sat(deposit_value / 4),
+            &der_deposit_descriptor,
+            &vault.deposit_descriptor,
+            &vault.unvault_descriptor,
+            &vault.cpfp_descriptor,
+            2,
+            0,
+            secp,
+        )
+        .unwrap();
+        let outputs = &spend_tx.tx().output;
+        assert_eq!(outputs.len(), 3);
+        assert_eq!(outputs[1].value, deposit_value / 4);
+        assert_eq!(outputs[1].script_pubkey, recipient.script_pubkey());
+        assert_eq!(
+            spend_tx.change_amount(&der_deposit_descriptor),
+            Some(Amount::from_sat(outputs[2].value))
+        );
+        assert_eq!(spend_tx.fees().as_sat(), 2 * spend_tx.max_weight());
+
+        // There must be enough left for the change to not be dust
+        assert_eq!(
+            spend_to_single_recipient(
+                deposits,
+                recipient,
+                Amount::from_sat(deposit_value),
+                &der_deposit_descriptor,
+                &vault.deposit_descriptor,
+                &vault.unvault_descriptor,
+                &vault.cpfp_descriptor,
+                2,
+                0,
+                secp,
+            ),
+            Err(TransactionCreationError::InsufficientFunds)
+        );
+    }
+
+    #[test]
+    fn spend_dedicated_cpfp_descriptor() {
+        let vault = test_vault();
+        let secp = &vault.secp;
+        let deposits = vec![(
+            TestVault::default_outpoint(),
+            Amount::from_sat(1_000_000),
+            vault.child_number,
+        )];
+        let spend_txos = vec![SpendTxOut::new(TxOut {
+            value: 500_000,
+            script_pubkey: vault
+                .deposit_descriptor
+                .derive(other_child_number(vault.child_number), secp)
+                .inner()
+                .script_pubkey(),
+        })];
+        // The Spend CPFP output is controlled by the stakeholders, not the managers
+        let spend_cpfp_descriptor = CpfpDescriptor::new(
+            vault
+                .stakeholders_priv
+                .iter()
+                .map(|xpriv| {
+                    DescriptorPublicKey::XPub(DescriptorXKey {
+                        origin: None,
+                        xkey: bip32::ExtendedPubKey::from_private(secp, xpriv),
+                        derivation_path: bip32::DerivationPath::from(vec![]),
+                        wildcard: Wildcard::Unhardened,
+                    })
+                })
+                .collect(),
+        )
+        .unwrap();
+
+        let spend_tx = spend_tx_from_deposits_with_cpfp(
+            deposits.clone(),
+            spend_txos.clone(),
+            None,
+            &vault.deposit_descriptor,
+            &vault.unvault_descriptor,
+            &vault.cpfp_descriptor,
+            &spend_cpfp_descriptor,
+            0,
+            false,
+            secp,
+        )
+        .unwrap();
+        let der_spend_cpfp_descriptor = spend_cpfp_descriptor.derive(vault.child_number, secp);
+        assert_eq!(
+            spend_tx.tx().output[0].script_pubkey,
+            der_spend_cpfp_descriptor.inner().script_pubkey()
+        );
+        assert_eq!(
+            spend_tx.check_cpfp_txout(&der_spend_cpfp_descriptor),
+            Ok(())
+        );
+        assert!(spend_tx.cpfp_txin(&spend_cpfp_descriptor, secp).is_some());
+        assert!(spend_tx.cpfp_txin(&vault.cpfp_descriptor, secp).is_none());
+        // It spends the very same Unvaults, that still pay to the managers' CPFP descriptor
+        let spend_tx_same_cpfp = spend_tx_from_deposits(
+            deposits,
+            spend_txos,
+            None,
+            &vault.deposit_descriptor,
+            &vault.unvault_descriptor,
+            &vault.cpfp_descriptor,
+            0,
+            false,
+            secp,
+        )
+        .unwrap();
+        assert_eq!(
+            spend_tx_same_cpfp.tx().output[0].script_pubkey,
+            vault.der_cpfp_descriptor().inner().script_pubkey()
+        );
+        assert_eq!(spend_tx.tx().input, spend_tx_same_cpfp.tx().input);
+    }
+
+    #[test]
+    fn spend_from_unvaults() {
+        let vault = test_vault();
+        let secp = &vault.secp;
+        let deposit_outpoint = TestVault::default_outpoint();
+        let deposit_value = Amount::from_sat(1_000_000);
+        let chain = vault.chain(deposit_outpoint, deposit_value).unwrap();
+        let spend_txo = SpendTxOut::new(TxOut {
+            value: 500_000,
+            script_pubkey: vault
+                .deposit_descriptor
+                .derive(other_child_number(vault.child_number), secp)
+                .inner()
+                .script_pubkey(),
+        });
+
+        // We get the same Spend out of the already created Unvault
+        let spend_tx = spend_tx_from_deposits(
+            vec![(deposit_outpoint, deposit_value, vault.child_number)],
+            vec![spend_txo.clone()],
+            None,
+            &vault.deposit_descriptor,
+            &vault.unvault_descriptor,
+            &vault.cpfp_descriptor,
+            0,
+            false,
+            secp,
+        )
+        .unwrap();
+        assert_eq!(
+            spend_tx_from_unvaults(
+                &[(chain.unvault().clone(), vault.child_number)],
+                vec![spend_txo.clone()],
+                None,
+                &vault.unvault_descriptor,
+                &vault.cpfp_descriptor,
+                0,
+                false,
+                secp,
+            ),
+            Ok(spend_tx)
+        );
+
+        // The Unvault must pay to the Unvault descriptor at the given index
+        assert_eq!(
+            spend_tx_from_unvaults(
+                &[(
+                    chain.unvault().clone(),
+                    other_child_number(vault.child_number)
+                )],
+                vec![spend_txo],
+                None,
+                &vault.unvault_descriptor,
+                &vault.cpfp_descriptor,
+                0,
+                false,
+                secp,
+            ),
+            Err(TransactionCreationError::MissingUnvaultTxOut)
+        );
+    }
+
+    #[test]
+    fn rbf_schedule() {
+        let vault = test_vault();
+        let secp = &vault.secp;
+        let deposit_outpoint = TestVault::default_outpoint();
+        let deposits = vec![(
+            deposit_outpoint,
+            Amount::from_sat(1_000_000),
+            vault.child_number,
+        )];
+        let der_deposit_descriptor = vault.der_deposit_descriptor();
+        let recipient = &Address::p2wsh(
+            &vault
+                .deposit_descriptor
+                .derive(other_child_number(vault.child_number), secp)
+                .inner()
+                .explicit_script(),
+            Network::Bitcoin,
+        );
+        let base_spend = spend_to_single_recipient(
+            deposits.clone(),
+            recipient,
+            Amount::from_sat(250_000),
+            &der_deposit_descriptor,
+            &vault.deposit_descriptor,
+            &vault.unvault_descriptor,
+            &vault.cpfp_descriptor,
+            2,
+            0,
+            secp,
+        )
+        .unwrap();
+        let spend_weight = base_spend.max_weight();
+        let change_value = base_spend
+            .change_amount(&der_deposit_descriptor)
+            .unwrap()
+            .as_sat();
+
+        // We can create replacements paying higher fees out of the change
+        let schedule = spend_rbf_schedule(&base_spend, &[3, 5], &der_deposit_descriptor).unwrap();
+        assert_eq!(schedule.len(), 2);
+        for (replacement, feerate) in schedule.iter().zip([3, 5].iter()) {
+            assert_eq!(replacement.fees().as_sat(), feerate * spend_weight);
+            assert_eq!(replacement.max_weight(), spend_weight);
+            assert_eq!(
+                replacement.change_amount(&der_deposit_descriptor),
+                Some(Amount::from_sat(
+                    change_value - (feerate - 2) * spend_weight
+                ))
+            );
+            assert!(base_spend.conflicts_with(replacement));
+        }
+        // The replacements pay for their own size on top of the replaced fees
+        assert!(schedule[0].meets_rbf_replacement(&[&base_spend], INCREMENTAL_RELAY_FEERATE));
+        assert!(schedule[1].meets_rbf_replacement(&[&schedule[0]], INCREMENTAL_RELAY_FEERATE));
+        assert!(!schedule[1]
+            .meets_rbf_replacement(&[&base_spend, &schedule[0]], INCREMENTAL_RELAY_FEERATE));
+        assert!(!schedule[0].meets_rbf_replacement(&[&base_spend], 5));
+        assert!(!base_spend.meets_rbf_replacement(&[&base_spend], INCREMENTAL_RELAY_FEERATE));
+
+        // Each replacement must replace the previous one
+        for feerates in [[2, 3], [5, 2], [5, 3], [3, 3]].iter() {
+            assert!(matches!(
+                spend_rbf_schedule(&base_spend, feerates, &der_deposit_descriptor),
+                Err(Error::TransactionCreation(
+                    TransactionCreationError::FeerateTooLow
+                ))
+            ));
+        }
+
+        // There must be enough left for the change to not be dust
+        let exhausting_feerate = 2 + change_value / spend_weight + 1;
+        assert!(exhausting_feerate * spend_weight <= INSANE_FEES);
+        assert!(matches!(
+            spend_rbf_schedule(&base_spend, &[exhausting_feerate], &der_deposit_descriptor),
+            Err(Error::TransactionCreation(TransactionCreationError::Dust))
+        ));
+        assert!(matches!(
+            spend_rbf_schedule(&base_spend, &[INSANE_FEES], &der_deposit_descriptor),
+            Err(Error::TransactionCreation(
+                TransactionCreationError::InsaneFees
+            ))
+        ));
+
+        // A Spend without change can't be fee-bumped out of it
+        let no_change_spend = spend_tx_from_deposits(
+            deposits,
+            vec![SpendTxOut::new(TxOut {
+                value: 900_000,
+                script_pubkey: recipient.script_pubkey(),
+            })],
+            None,
+            &vault.deposit_descriptor,
+            &vault.unvault_descriptor,
+            &vault.cpfp_descriptor,
+            0,
+            true,
+            secp,
+        )
+        .unwrap();
+        assert!(matches!(
+            spend_rbf_schedule(&no_change_spend, &[3], &der_deposit_descriptor),
+            Err(Error::TransactionCreation(
+                TransactionCreationError::InsufficientFunds
+            ))
+        ));
+    }
+
+    #[test]
+    fn watchonly_psbt() {
+        let secp = secp256k1::Secp256k1::new();
+        let rng = fastrand::Rng::with_seed(1401);
+        // The stakeholders' descriptors use account-level xpubs, derived from their master key
+        let account_path = bip32::DerivationPath::from_str("m/48'/1'/0'/2'").unwrap();
+        let masters: Vec<bip32::ExtendedPrivKey> = (0..2)
+            .map(|_| {
+                let seed: Vec<u8> = (0..32).map(|_| rng.u8(..)).collect();
+                bip32::ExtendedPrivKey::new_master(Network::Bitcoin, &seed).unwrap()
+            })
+            .collect();
+        let accounts: Vec<bip32::ExtendedPubKey> = masters
+            .iter()
+            .map(|master| {
+                bip32::ExtendedPubKey::from_private(
+                    &secp,
+                    &master.derive_priv(&secp, &account_path).unwrap(),
+                )
+            })
+            .collect();
+        let deposit_descriptor = DepositDescriptor::new(
+            accounts
+                .iter()
+                .map(|xpub| {
+                    DescriptorPublicKey::XPub(DescriptorXKey {
+                        origin: None,
+                        xkey: *xpub,
+                        derivation_path: bip32::DerivationPath::from(vec![]),
+                        wildcard: Wildcard::Unhardened,
+                    })
+                })
+                .collect(),
+        )
+        .unwrap();
+        let child_number = bip32::ChildNumber::from(42);
+        let der_deposit_descriptor = deposit_descriptor.derive(child_number, &secp);
+        let key_origins: BTreeMap<bip32::Fingerprint, bip32::KeySource> = masters
+            .iter()
+            .zip(accounts.iter())
+            .map(|(master, account)| {
+                (
+                    account.fingerprint(),
+                    (master.fingerprint(&secp), account_path.clone()),
+                )
+            })
+            .collect();
+
+        // An Emergency transaction paying back to the deposit descriptor, so we know about both
+        // its input and its output
+        let deposit_outpoint = TestVault::default_outpoint();
+        let emergency_address = EmergencyAddress::from(Address::p2wsh(
+            &der_deposit_descriptor.inner().explicit_script(),
+            Network::Bitcoin,
+        ))
+        .unwrap();
+        let emergency_tx = EmergencyTransaction::new(
+            DepositTxIn::new(
+                deposit_outpoint,
+                DepositTxOut::new(Amount::from_sat(COIN_VALUE), &der_deposit_descriptor),
+            ),
+            emergency_address,
+        )
+        .unwrap();
+        assert!(emergency_tx.psbt().outputs[0].bip32_derivation.is_empty());
+
+        let watchonly_psbt: Psbt = encode::deserialize(
+            &base64::decode(
+                emergency_tx
+                    .to_watchonly_psbt(&[der_deposit_descriptor.inner()], &key_origins)
+                    .unwrap(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            watchonly_psbt.inputs[0].sighash_type,
+            Some(SigHashType::All)
+        );
+        assert_eq!(
+            watchonly_psbt.outputs[0].witness_script,
+            Some(der_deposit_descriptor.inner().explicit_script())
+        );
+        // All the keys can be derived from the master keys at the recorded path
+        let full_path = account_path.child(child_number);
+        for bip32_derivation in [
+            &watchonly_psbt.inputs[0].bip32_derivation,
+            &watchonly_psbt.outputs[0].bip32_derivation,
+        ]
+        .iter()
+        {
+            assert_eq!(bip32_derivation.len(), masters.len());
+            for (pubkey, (fingerprint, der_path)) in bip32_derivation.iter() {
+                assert_eq!(der_path, &full_path);
+                let master = masters
+                    .iter()
+                    .find(|master| master.fingerprint(&secp) == *fingerprint)
+                    .expect("The fingerprint of one of the master keys");
+                assert_eq!(
+                    bip32::ExtendedPubKey::from_private(
+                        &secp,
+                        &master.derive_priv(&secp, der_path).unwrap()
+                    )
+                    .public_key,
+                    *pubkey
+                );
+            }
+        }
+
+        // We don't fill the outputs we don't have a descriptor for
+        let watchonly_psbt: Psbt = encode::deserialize(
+            &base64::decode(emergency_tx.to_watchonly_psbt(&[], &key_origins).unwrap()).unwrap(),
+        )
+        .unwrap();
+        assert!(watchonly_psbt.outputs[0].witness_script.is_none());
+        assert!(watchonly_psbt.outputs[0].bip32_derivation.is_empty());
+
+        // The origin of all the keys must be known
+        let mut partial_key_origins = key_origins.clone();
+        partial_key_origins.remove(&accounts[0].fingerprint());
+        assert!(matches!(
+            emergency_tx.to_watchonly_psbt(&[der_deposit_descriptor.inner()], &partial_key_origins),
+            Err(Error::TransactionSerialisation(
+                TransactionSerialisationError::Validation(PsbtValidationError::InvalidInputField(
+                    _
+                ))
+            ))
+        ));
+    }
+
+    #[test]
+    fn weight_overflow() {
+        assert_eq!(utils::sum_weights(vec![1_000, 272, 42]), Ok(1_314));
+        assert_eq!(
+            utils::sum_weights(vec![1_000, usize::MAX, 42]),
+            Err(TransactionCreationError::WeightOverflow)
+        );
+    }
+
     // Small sanity checks, see fuzzing targets for more.
     #[cfg(feature = "use-serde")]
     #[test]
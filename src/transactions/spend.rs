@@ -13,11 +13,12 @@ use miniscript::{
     bitcoin::{
         blockdata::constants::max_money,
         consensus::encode::Decodable,
+        secp256k1,
         util::psbt::{
             Global as PsbtGlobal, Input as PsbtIn, Output as PsbtOut,
             PartiallySignedTransaction as Psbt,
         },
-        Amount, Network, OutPoint, Transaction,
+        Address, Amount, Network, OutPoint, Transaction, TxOut,
     },
     DescriptorTrait,
 };
@@ -31,6 +32,7 @@ use {
 use std::{
     collections::{BTreeMap, HashSet},
     convert::TryInto,
+    io,
 };
 
 impl_revault_transaction!(
@@ -45,6 +47,9 @@ impl SpendTransaction {
     /// The insane fees check is gated behind the `insane_fee_checks` parameter as the caller
     /// may want to create a transaction without a change output.
     ///
+    /// The `cpfp_descriptor` need not be the one used for the Unvault transactions' CPFP
+    /// outputs, the Spend CPFP output may be controlled by a different set of keys.
+    ///
     /// BIP174 Creator and Updater roles.
     pub fn new(
         unvault_inputs: Vec<UnvaultTxIn>,
@@ -54,12 +59,33 @@ impl SpendTransaction {
         lock_time: u32,
         insane_fee_check: bool,
     ) -> Result<SpendTransaction, TransactionCreationError> {
+        for txin in unvault_inputs.iter() {
+            utils::check_witness_script_size(txin.txout().witness_script())?;
+        }
+
+        // Only a single null-data output is standard
+        if spend_txouts
+            .iter()
+            .filter(|txo| txo.txout().script_pubkey.is_op_return())
+            .count()
+            > 1
+        {
+            return Err(TransactionCreationError::MultipleOpReturn);
+        }
+
         // Check for duplicated inputs
         let uniq_txins: HashSet<OutPoint> = unvault_inputs.iter().map(|i| i.outpoint()).collect();
         if uniq_txins.len() != unvault_inputs.len() {
             return Err(TransactionCreationError::DuplicatedInput);
         }
 
+        // Used later to check the maximum transaction size.
+        let sat_weight = utils::sum_weights(
+            unvault_inputs
+                .iter()
+                .map(|txin| txin.txout().max_sat_weight()),
+        )?;
+
         // The CPFP is tricky to compute. We could be smart and avoid some allocations here
         // but at the cost of clarity.
         let cpfp_txo = SpendTransaction::cpfp_txout(
@@ -68,16 +94,15 @@ impl SpendTransaction {
             change_txout.clone(),
             cpfp_descriptor,
             lock_time,
-        );
-
-        // Used later to check the maximum transaction size.
-        let sat_weight = unvault_inputs
-            .iter()
-            .map(|txin| txin.txout().max_sat_weight())
-            .sum::<usize>();
+        )?;
 
         // Record the value spent and sent
-        let mut value_in: u64 = 0;
+        let value_in = unvault_inputs
+            .iter()
+            .try_fold(0u64, |sum, txin| {
+                sum.checked_add(txin.txout().txout().value)
+            })
+            .ok_or(TransactionCreationError::InsaneAmounts)?;
         let mut value_out: u64 = 0;
 
         let mut txos = if change_txout.is_some() {
@@ -98,7 +123,9 @@ impl SpendTransaction {
                 return Err(TransactionCreationError::Dust);
             }
 
-            value_out += txo.value;
+            value_out = value_out
+                .checked_add(txo.value)
+                .ok_or(TransactionCreationError::InsaneAmounts)?;
 
             txos.push(txo);
             psbtouts.push(PsbtOut::default());
@@ -116,7 +143,9 @@ impl SpendTransaction {
                 return Err(TransactionCreationError::Dust);
             }
 
-            value_out += txo.value;
+            value_out = value_out
+                .checked_add(txo.value)
+                .ok_or(TransactionCreationError::InsaneAmounts)?;
 
             txos.push(txo);
             psbtouts.push(psbtout);
@@ -142,7 +171,6 @@ impl SpendTransaction {
                 .into_iter()
                 .map(|input| {
                     let prev_txout = input.into_txout();
-                    value_in += prev_txout.txout().value;
                     PsbtIn {
                         witness_script: Some(prev_txout.witness_script().clone()),
                         bip32_derivation: prev_txout.bip32_derivation().clone(),
@@ -159,7 +187,7 @@ impl SpendTransaction {
         let witstrip_weight = unsigned_tx.get_weight();
         let total_weight = sat_weight
             .checked_add(witstrip_weight)
-            .expect("Weight computation bug: cannot overflow");
+            .ok_or(TransactionCreationError::WeightOverflow)?;
         if total_weight > MAX_STANDARD_TX_WEIGHT as usize {
             return Err(TransactionCreationError::TooLarge);
         }
@@ -183,13 +211,15 @@ impl SpendTransaction {
     /// The CPFP output value is dependant on the transaction size, see [practical-revaul
     /// t](https://github.com/revault/practical-revault/blob/master/transactions.md#spend_tx) for
     /// more details.
+    ///
+    /// Will error if the weight or the CPFP output value computation overflows.
     pub fn cpfp_txout(
         unvault_inputs: Vec<UnvaultTxIn>,
         spend_txouts: Vec<SpendTxOut>,
         change_txout: Option<DepositTxOut>,
         cpfp_descriptor: &DerivedCpfpDescriptor,
         lock_time: u32,
-    ) -> CpfpTxOut {
+    ) -> Result<CpfpTxOut, TransactionCreationError> {
         let mut txos = Vec::with_capacity(spend_txouts.len() + 1);
         let dummy_cpfp_txo = CpfpTxOut::new(Amount::from_sat(u64::MAX), &cpfp_descriptor);
         txos.push(dummy_cpfp_txo.txout().clone());
@@ -213,30 +243,242 @@ impl SpendTransaction {
             output: txos,
         };
 
-        let sat_weight: u64 = unvault_inputs
-            .iter()
-            .map(|txin| txin.txout().max_sat_weight())
-            .sum::<usize>()
-            .try_into()
-            .expect("An usize doesn't fit in an u64?");
-        let witstrip_weight: u64 = dummy_tx
-            .get_weight()
+        let total_weight = utils::sum_weights(
+            unvault_inputs
+                .iter()
+                .map(|txin| txin.txout().max_sat_weight())
+                .chain(std::iter::once(dummy_tx.get_weight())),
+        )?;
+        let total_weight: u64 = total_weight
             .try_into()
-            .expect("Bug: an usize that doesn't fit in a u64?");
-        let total_weight = sat_weight
-            .checked_add(witstrip_weight)
-            .expect("Weight computation bug: cannot overflow");
+            .map_err(|_| TransactionCreationError::WeightOverflow)?;
+
+        // See https://github.com/revault/practical-revault/blob/master/transactions.md#spend_tx
+        // for this arbirtrary value.
+        let cpfp_value = total_weight
+            .checked_mul(16)
+            .ok_or(TransactionCreationError::InsaneAmounts)?;
+        Ok(CpfpTxOut::new(
+            Amount::from_sat(cpfp_value),
+            &cpfp_descriptor,
+        ))
+    }
+
+    /// Add an output paying `value` to this `address`. The CPFP output value is updated to
+    /// account for the increased transaction size.
+    ///
+    /// Will error with [Error::AlreadySigned] if any input was already signed, as the new output
+    /// would invalidate the signatures, and with [TransactionCreationError::MissingCpfpTxOut] if
+    /// the first output doesn't pay to this CPFP descriptor.
+    pub fn add_destination(
+        &mut self,
+        address: &Address,
+        value: Amount,
+        cpfp_descriptor: &DerivedCpfpDescriptor,
+    ) -> Result<(), Error> {
+        if self.has_signatures() {
+            return Err(Error::AlreadySigned);
+        }
+
+        // The CPFP output is always the first one, don't update the value of another output.
+        if self.tx().output[0].script_pubkey != cpfp_descriptor.inner().script_pubkey() {
+            return Err(TransactionCreationError::MissingCpfpTxOut.into());
+        }
+
+        let txo = TxOut {
+            value: value.as_sat(),
+            script_pubkey: address.script_pubkey(),
+        };
+        if txo.value < txo.script_pubkey.dust_value().as_sat() {
+            return Err(TransactionCreationError::Dust.into());
+        }
+
+        let mut psbt = self.psbt().clone();
+        psbt.global.unsigned_tx.output.push(txo);
+        psbt.outputs.push(PsbtOut::default());
+        SpendTransaction::update_cpfp_value(&mut psbt)?;
+
+        self.0 = psbt;
+        Ok(())
+    }
+
+    /// Recompute the value of the CPFP output from the current transaction size, for instance
+    /// after the Spend was modified.
+    ///
+    /// Will error with [Error::AlreadySigned] if any input was already signed, as changing the
+    /// CPFP output value would invalidate the signatures.
+    pub fn recompute_cpfp_value(
+        &mut self,
+        cpfp_descriptor: &DerivedCpfpDescriptor,
+    ) -> Result<(), Error> {
+        if self.has_signatures() {
+            return Err(Error::AlreadySigned);
+        }
+
+        // The CPFP output is always the first one.
+        if self.tx().output[0].script_pubkey != cpfp_descriptor.inner().script_pubkey() {
+            return Err(TransactionCreationError::MissingCpfpTxOut.into());
+        }
+
+        let mut psbt = self.psbt().clone();
+        SpendTransaction::update_cpfp_value(&mut psbt)?;
+
+        self.0 = psbt;
+        Ok(())
+    }
+
+    /// Check the first output of a (parsed) Spend transaction pays to this CPFP descriptor, as
+    /// for the Spends we create. Allows to then reliably refer to `output[0]` as the CPFP output.
+    pub fn check_cpfp_txout(
+        &self,
+        cpfp_descriptor: &DerivedCpfpDescriptor,
+    ) -> Result<(), PsbtValidationError> {
+        if self.tx().output[0].script_pubkey != cpfp_descriptor.inner().script_pubkey() {
+            return Err(PsbtValidationError::CpfpNotFirst);
+        }
+
+        Ok(())
+    }
+
+    /// Check all the inputs of this (parsed) Spend transaction spend an Unvault output of this
+    /// `unvault_descriptor`, to detect an unrelated input smuggled into a batch Spend.
+    ///
+    /// Errors with [PsbtValidationError::FinalizedInput] on a finalized input, as its derivation
+    /// path was wiped. This is meant to be used before signing.
+    pub fn check_unvault_descriptor(
+        &self,
+        unvault_descriptor: &UnvaultDescriptor,
+        secp: &secp256k1::Secp256k1<impl secp256k1::Verification>,
+    ) -> Result<(), PsbtValidationError> {
+        for (i, psbtin) in self.psbt().inputs.iter().enumerate() {
+            if psbtin.final_script_witness.is_some() {
+                return Err(PsbtValidationError::FinalizedInput(i));
+            }
+            SpendTransaction::unvault_input_descriptor(psbtin, unvault_descriptor, secp)?;
+        }
+
+        Ok(())
+    }
+
+    // Get the Unvault descriptor derived at the index of the derivation path of this PSBT input,
+    // checking it really spends an output paying to it.
+    fn unvault_input_descriptor(
+        psbtin: &PsbtIn,
+        unvault_descriptor: &UnvaultDescriptor,
+        secp: &secp256k1::Secp256k1<impl secp256k1::Verification>,
+    ) -> Result<DerivedUnvaultDescriptor, PsbtValidationError> {
+        // We always set a derivation path with a depth of 1
+        let der_index = match psbtin.bip32_derivation.values().next() {
+            Some((_, der_path)) if der_path.len() == 1 => der_path[0],
+            _ => return Err(PsbtValidationError::MixedUnvaultDescriptors),
+        };
+        let der_unvault_desc = unvault_descriptor.derive(der_index, secp);
+
+        let spk = &psbtin
+            .witness_utxo
+            .as_ref()
+            .expect("Checked by the common parsing checks")
+            .script_pubkey;
+        if *spk != der_unvault_desc.inner().script_pubkey() {
+            return Err(PsbtValidationError::MixedUnvaultDescriptors);
+        }
+
+        Ok(der_unvault_desc)
+    }
+
+    /// Rebuild the Unvault inputs of this (parsed) Spend transaction out of their PSBT inputs,
+    /// checking they all spend an Unvault output of this `unvault_descriptor`.
+    ///
+    /// Errors with [PsbtValidationError::FinalizedInput] on a finalized input, as its derivation
+    /// path was wiped.
+    pub fn unvault_txins(
+        &self,
+        unvault_descriptor: &UnvaultDescriptor,
+        secp: &secp256k1::Secp256k1<impl secp256k1::Verification>,
+    ) -> Result<Vec<UnvaultTxIn>, PsbtValidationError> {
+        let psbt = self.psbt();
+        psbt.inputs
+            .iter()
+            .zip(psbt.global.unsigned_tx.input.iter())
+            .enumerate()
+            .map(|(i, (psbtin, txin))| {
+                if psbtin.final_script_witness.is_some() {
+                    return Err(PsbtValidationError::FinalizedInput(i));
+                }
+                let der_unvault_desc =
+                    SpendTransaction::unvault_input_descriptor(psbtin, unvault_descriptor, secp)?;
+
+                let prev_value = psbtin
+                    .witness_utxo
+                    .as_ref()
+                    .expect("Checked by the common parsing checks")
+                    .value;
+                Ok(UnvaultTxIn::new(
+                    txin.previous_output,
+                    UnvaultTxOut::new(Amount::from_sat(prev_value), &der_unvault_desc),
+                    txin.sequence,
+                ))
+            })
+            .collect()
+    }
+
+    /// Get the value of the change output paying to this deposit descriptor, if any.
+    pub fn change_amount(&self, deposit_descriptor: &DerivedDepositDescriptor) -> Option<Amount> {
+        let spk = deposit_descriptor.inner().script_pubkey();
+        self.tx()
+            .output
+            .iter()
+            .find(|txo| txo.script_pubkey == spk)
+            .map(|txo| Amount::from_sat(txo.value))
+    }
 
+    fn has_signatures(&self) -> bool {
+        self.psbt()
+            .inputs
+            .iter()
+            .any(|psbtin| !psbtin.partial_sigs.is_empty() || psbtin.final_script_witness.is_some())
+    }
+
+    // Set the value of the first (CPFP) output from the transaction size, and check the resulting
+    // amounts.
+    fn update_cpfp_value(psbt: &mut Psbt) -> Result<(), TransactionCreationError> {
+        let total_weight = utils::max_weight(psbt);
+        if total_weight > MAX_STANDARD_TX_WEIGHT as u64 {
+            return Err(TransactionCreationError::TooLarge);
+        }
         // See https://github.com/revault/practical-revault/blob/master/transactions.md#spend_tx
         // for this arbirtrary value.
-        let cpfp_value = 16 * total_weight;
-        CpfpTxOut::new(Amount::from_sat(cpfp_value), &cpfp_descriptor)
+        psbt.global.unsigned_tx.output[0].value = total_weight
+            .checked_mul(16)
+            .ok_or(TransactionCreationError::InsaneAmounts)?;
+
+        let value_out = psbt
+            .global
+            .unsigned_tx
+            .output
+            .iter()
+            .try_fold(0u64, |total, txo| total.checked_add(txo.value))
+            .ok_or(TransactionCreationError::InsaneAmounts)?;
+        if value_out > max_money(Network::Bitcoin) {
+            return Err(TransactionCreationError::InsaneAmounts);
+        }
+        if utils::psbt_fees(psbt).is_none() {
+            return Err(TransactionCreationError::NegativeFees);
+        }
+
+        Ok(())
     }
 
     // FIXME: feerate sanity checks
     /// Parse a Spend transaction from a PSBT
     pub fn from_raw_psbt(raw_psbt: &[u8]) -> Result<Self, TransactionSerialisationError> {
-        let psbt = Decodable::consensus_decode(raw_psbt)?;
+        SpendTransaction::from_reader(raw_psbt)
+    }
+
+    /// Parse a Spend transaction from a PSBT read from this `reader`, without having to
+    /// buffer it in a byte slice first.
+    pub fn from_reader<R: io::Read>(reader: R) -> Result<Self, TransactionSerialisationError> {
+        let psbt = Decodable::consensus_decode(reader)?;
         let psbt = utils::psbt_common_sanity_checks(psbt)?;
 
         if psbt.inputs.is_empty() {
@@ -304,6 +546,19 @@ impl SpendTransaction {
             );
         }
 
+        // At most a single null-data output
+        if psbt
+            .global
+            .unsigned_tx
+            .output
+            .iter()
+            .filter(|txo| txo.script_pubkey.is_op_return())
+            .count()
+            > 1
+        {
+            return Err(PsbtValidationError::MultipleOpReturn.into());
+        }
+
         // Make sure the transaction cannot get out of standardness bounds once finalized
         let spend_tx = SpendTransaction(psbt);
         let witstrip_weight = spend_tx.psbt().global.unsigned_tx.get_weight();
@@ -320,42 +575,116 @@ impl SpendTransaction {
 
 impl CpfpableTransaction for SpendTransaction {
     fn max_weight(&self) -> u64 {
-        let psbt = self.psbt();
-        let tx = &psbt.global.unsigned_tx;
+        utils::max_weight(self.psbt())
+    }
+}
 
-        let mut weight: u64 = tx.get_weight().try_into().expect("Can't be >u64::MAX");
-        for txin in psbt.inputs.iter() {
-            let txin_weight: u64 = if self.is_finalized() {
-                txin.final_script_witness
-                    .as_ref()
-                    .expect("Always set if final")
-                    .iter()
-                    .map(|e| e.len())
-                    .sum::<usize>()
-                    .try_into()
-                    .expect("Bug: witness size >u64::MAX")
-            } else {
-                // FIXME: this panic can probably be triggered...
-                miniscript::descriptor::Wsh::new(
-                    miniscript::Miniscript::parse(
-                        txin.witness_script
-                            .as_ref()
-                            .expect("Unvault txins always have a witness Script"),
-                    )
-                    .expect("UnvaultTxIn witness_script is created from a Miniscript"),
-                )
-                .expect("")
-                .max_satisfaction_weight()
-                .expect("It's a sane Script, derived from a Miniscript")
-                .try_into()
-                .expect("Can't be >u64::MAX")
-            };
-            weight = weight
-                .checked_add(txin_weight)
-                .expect("Weight computation bug: overflow computing spent coins value");
-        }
-        assert!(weight > 0, "We never create an empty tx");
+#[cfg(test)]
+mod tests {
+    use super::SpendTransaction;
+    use crate::{
+        error::*,
+        transactions::{
+            spend_to_single_recipient,
+            test_utils::{test_vault, TestVault},
+            tests_helpers::other_child_number,
+            RevaultTransaction,
+        },
+    };
+
+    use miniscript::{
+        bitcoin::{consensus::encode, Address, Amount, Network},
+        DescriptorTrait,
+    };
+
+    // A Spend of a single deposit of this vault, with change
+    fn spend_tx(vault: &TestVault) -> SpendTransaction {
+        let deposit_outpoint = TestVault::default_outpoint();
+        let recipient = Address::p2wsh(
+            &vault
+                .deposit_descriptor
+                .derive(other_child_number(vault.child_number), &vault.secp)
+                .inner()
+                .explicit_script(),
+            Network::Bitcoin,
+        );
+        spend_to_single_recipient(
+            vec![(
+                deposit_outpoint,
+                Amount::from_sat(1_000_000),
+                vault.child_number,
+            )],
+            &recipient,
+            Amount::from_sat(250_000),
+            &vault.der_deposit_descriptor(),
+            &vault.deposit_descriptor,
+            &vault.unvault_descriptor,
+            &vault.cpfp_descriptor,
+            2,
+            0,
+            &vault.secp,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn spend_add_destination_cpfp_first() {
+        let vault = test_vault();
+        let spend_tx = spend_tx(&vault);
+        let dest_addr = Address::p2wsh(
+            &vault.der_deposit_descriptor().inner().explicit_script(),
+            Network::Bitcoin,
+        );
+
+        // A parsed Spend whose first output is a recipient, not the CPFP output
+        let mut psbt = spend_tx.psbt().clone();
+        psbt.global.unsigned_tx.output.swap(0, 1);
+        psbt.outputs.swap(0, 1);
+        let mut swapped_spend_tx =
+            SpendTransaction::from_raw_psbt(&encode::serialize(&psbt)).unwrap();
+        let recipient_value = swapped_spend_tx.tx().output[0].value;
+        assert!(matches!(
+            swapped_spend_tx.add_destination(
+                &dest_addr,
+                Amount::from_sat(1_000),
+                &vault.der_cpfp_descriptor()
+            ),
+            Err(Error::TransactionCreation(
+                TransactionCreationError::MissingCpfpTxOut
+            ))
+        ));
+        assert_eq!(swapped_spend_tx.tx().output[0].value, recipient_value);
+    }
 
-        weight
+    #[test]
+    fn spend_recompute_cpfp_value() {
+        let vault = test_vault();
+        let spend_tx = spend_tx(&vault);
+
+        // Recomputing the CPFP output value of an unchanged Spend is a no-op
+        let mut spend_tx_recomputed = spend_tx.clone();
+        spend_tx_recomputed
+            .recompute_cpfp_value(&vault.der_cpfp_descriptor())
+            .unwrap();
+        assert_eq!(spend_tx_recomputed, spend_tx);
+        let other_cpfp_descriptor = vault
+            .cpfp_descriptor
+            .derive(other_child_number(vault.child_number), &vault.secp);
+        assert!(matches!(
+            spend_tx_recomputed.recompute_cpfp_value(&other_cpfp_descriptor),
+            Err(Error::TransactionCreation(
+                TransactionCreationError::MissingCpfpTxOut
+            ))
+        ));
+
+        // It would invalidate the signatures
+        let sigs = vault.signatures(&spend_tx, 0, &vault.managers_priv);
+        spend_tx_recomputed
+            .add_signatures(0, sigs, &vault.secp)
+            .unwrap();
+        assert!(matches!(
+            spend_tx_recomputed.recompute_cpfp_value(&vault.der_cpfp_descriptor()),
+            Err(Error::AlreadySigned)
+        ));
     }
 }
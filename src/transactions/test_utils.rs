@@ -0,0 +1,301 @@
+//! # Test utilities
+//!
+//! A seedable builder of Revault vaults and their transactions, for downstream crates to use
+//! in their own tests. Gated behind the `test-utils` feature.
+
+use crate::{
+    error::*,
+    scripts::*,
+    transactions::{EmergencyAddress, RevaultTransaction, RevaultTransactionChain},
+    txins::*,
+    txouts::*,
+};
+
+use miniscript::{
+    bitcoin::{hashes::Hash, secp256k1, util::bip32, Address, Amount, Network, OutPoint, Txid},
+    descriptor::{DescriptorPublicKey, DescriptorXKey, Wildcard},
+    DescriptorTrait,
+};
+
+use std::{iter::repeat_with, str::FromStr};
+
+fn get_random_privkey(rng: &mut fastrand::Rng) -> bip32::ExtendedPrivKey {
+    let rand_bytes: Vec<u8> = repeat_with(|| rng.u8(..)).take(64).collect();
+
+    bip32::ExtendedPrivKey::new_master(Network::Bitcoin, &rand_bytes)
+        .unwrap_or_else(|_| get_random_privkey(rng))
+}
+
+// This generates the master private keys to derive directly from master, so it's
+// [None]<xpub_goes_here>m/* descriptor pubkeys
+pub(super) fn get_participants_sets(
+    n_stk: usize,
+    n_man: usize,
+    with_cosig_servers: bool,
+    rng: &mut fastrand::Rng,
+    secp: &secp256k1::Secp256k1<secp256k1::All>,
+) -> (
+    (Vec<bip32::ExtendedPrivKey>, Vec<DescriptorPublicKey>),
+    (Vec<bip32::ExtendedPrivKey>, Vec<DescriptorPublicKey>),
+    (Vec<bip32::ExtendedPrivKey>, Vec<DescriptorPublicKey>),
+    (Vec<bip32::ExtendedPrivKey>, Vec<DescriptorPublicKey>),
+) {
+    let mut managers_priv = Vec::with_capacity(n_man);
+    let mut managers = Vec::with_capacity(n_man);
+    let mut mancpfp_priv = Vec::with_capacity(n_man);
+    let mut mancpfp = Vec::with_capacity(n_man);
+    for i in 0..n_man {
+        managers_priv.push(get_random_privkey(rng));
+        managers.push(DescriptorPublicKey::XPub(DescriptorXKey {
+            origin: None,
+            xkey: bip32::ExtendedPubKey::from_private(&secp, &managers_priv[i]),
+            derivation_path: bip32::DerivationPath::from(vec![]),
+            wildcard: Wildcard::Unhardened,
+        }));
+
+        mancpfp_priv.push(get_random_privkey(rng));
+        mancpfp.push(DescriptorPublicKey::XPub(DescriptorXKey {
+            origin: None,
+            xkey: bip32::ExtendedPubKey::from_private(&secp, &mancpfp_priv[i]),
+            derivation_path: bip32::DerivationPath::from(vec![]),
+            wildcard: Wildcard::Unhardened,
+        }));
+    }
+
+    let mut stakeholders_priv = Vec::with_capacity(n_stk);
+    let mut stakeholders = Vec::with_capacity(n_stk);
+    let mut cosigners_priv = Vec::with_capacity(n_stk);
+    let mut cosigners = Vec::with_capacity(n_stk);
+    for i in 0..n_stk {
+        stakeholders_priv.push(get_random_privkey(rng));
+        stakeholders.push(DescriptorPublicKey::XPub(DescriptorXKey {
+            origin: None,
+            xkey: bip32::ExtendedPubKey::from_private(&secp, &stakeholders_priv[i]),
+            derivation_path: bip32::DerivationPath::from(vec![]),
+            wildcard: Wildcard::Unhardened,
+        }));
+
+        if with_cosig_servers {
+            cosigners_priv.push(get_random_privkey(rng));
+            cosigners.push(DescriptorPublicKey::XPub(DescriptorXKey {
+                origin: None,
+                xkey: bip32::ExtendedPubKey::from_private(&secp, &cosigners_priv[i]),
+                derivation_path: bip32::DerivationPath::from(vec![]),
+                wildcard: Wildcard::Unhardened,
+            }));
+        }
+    }
+
+    (
+        (managers_priv, managers),
+        (mancpfp_priv, mancpfp),
+        (stakeholders_priv, stakeholders),
+        (cosigners_priv, cosigners),
+    )
+}
+
+/// The keys and descriptors of a vault between stakeholders (each with a cosigning server) and
+/// managers, to create the transactions of a single deployment in tests.
+pub struct TestVault {
+    pub managers_priv: Vec<bip32::ExtendedPrivKey>,
+    pub mancpfp_priv: Vec<bip32::ExtendedPrivKey>,
+    pub stakeholders_priv: Vec<bip32::ExtendedPrivKey>,
+    pub cosigners_priv: Vec<bip32::ExtendedPrivKey>,
+    pub deposit_descriptor: DepositDescriptor,
+    pub unvault_descriptor: UnvaultDescriptor,
+    pub cpfp_descriptor: CpfpDescriptor,
+    pub emergency_address: EmergencyAddress,
+    pub child_number: bip32::ChildNumber,
+    pub secp: secp256k1::Secp256k1<secp256k1::All>,
+}
+
+impl TestVault {
+    /// Create a vault between `n_stk` stakeholders and `n_man` managers. The keys and the
+    /// derivation index are drawn from `rng`.
+    pub fn new(
+        n_stk: usize,
+        n_man: usize,
+        csv: u32,
+        rng: &mut fastrand::Rng,
+    ) -> Result<TestVault, Error> {
+        let secp = secp256k1::Secp256k1::new();
+        let child_number = bip32::ChildNumber::from(rng.u32(..1 << 31));
+
+        let (
+            (managers_priv, managers),
+            (mancpfp_priv, mancpfp),
+            (stakeholders_priv, stakeholders),
+            (cosigners_priv, cosigners),
+        ) = get_participants_sets(n_stk, n_man, true, rng, &secp);
+        let unvault_descriptor =
+            UnvaultDescriptor::new(stakeholders.clone(), managers, n_man, cosigners, csv)?;
+        let cpfp_descriptor = CpfpDescriptor::new(mancpfp)?;
+        let deposit_descriptor = DepositDescriptor::new(stakeholders)?;
+
+        // We reuse the deposit descriptor for the emergency address
+        let emergency_address = EmergencyAddress::from(Address::p2wsh(
+            &deposit_descriptor
+                .derive(child_number, &secp)
+                .inner()
+                .explicit_script(),
+            Network::Bitcoin,
+        ))
+        .expect("It's a P2WSH");
+
+        Ok(TestVault {
+            managers_priv,
+            mancpfp_priv,
+            stakeholders_priv,
+            cosigners_priv,
+            deposit_descriptor,
+            unvault_descriptor,
+            cpfp_descriptor,
+            emergency_address,
+            child_number,
+            secp,
+        })
+    }
+
+    /// The deposit outpoint used across unit tests
+    pub fn default_outpoint() -> OutPoint {
+        OutPoint::from_str("39a8212c6a9b467680d43e47b61b8363fe1febb761f9f548eb4a432b2bc9bbec:0")
+            .expect("Valid outpoint")
+    }
+
+    /// The deposit descriptor derived at this vault's index
+    pub fn der_deposit_descriptor(&self) -> DerivedDepositDescriptor {
+        self.deposit_descriptor
+            .derive(self.child_number, &self.secp)
+    }
+
+    /// The Unvault descriptor derived at this vault's index
+    pub fn der_unvault_descriptor(&self) -> DerivedUnvaultDescriptor {
+        self.unvault_descriptor
+            .derive(self.child_number, &self.secp)
+    }
+
+    /// The CPFP descriptor derived at this vault's index
+    pub fn der_cpfp_descriptor(&self) -> DerivedCpfpDescriptor {
+        self.cpfp_descriptor.derive(self.child_number, &self.secp)
+    }
+
+    /// Sign the input at `input_index` of this transaction with each of these `xprivs`, derived
+    /// at this vault's index.
+    pub fn signatures(
+        &self,
+        tx: &impl RevaultTransaction,
+        input_index: usize,
+        xprivs: &[bip32::ExtendedPrivKey],
+    ) -> Vec<(secp256k1::PublicKey, secp256k1::Signature)> {
+        let sighash = tx.signature_hash(input_index).expect("Input exists");
+        let msg = secp256k1::Message::from_slice(&sighash).expect("32 bytes");
+        let derivation_path = bip32::DerivationPath::from(vec![self.child_number]);
+
+        xprivs
+            .iter()
+            .map(|xpriv| {
+                let privkey = xpriv
+                    .derive_priv(&self.secp, &derivation_path)
+                    .expect("Unhardened derivation")
+                    .private_key;
+                (
+                    privkey.public_key(&self.secp).key,
+                    self.secp.sign(&msg, &privkey.key),
+                )
+            })
+            .collect()
+    }
+
+    /// A deposit txin of `deposit_value` for this vault
+    pub fn deposit_txin(&self, deposit_outpoint: OutPoint, deposit_value: Amount) -> DepositTxIn {
+        DepositTxIn::new(
+            deposit_outpoint,
+            DepositTxOut::new(deposit_value, &self.der_deposit_descriptor()),
+        )
+    }
+
+    /// The transaction chain of a deposit of `deposit_value` to this vault
+    pub fn chain(
+        &self,
+        deposit_outpoint: OutPoint,
+        deposit_value: Amount,
+    ) -> Result<RevaultTransactionChain, Error> {
+        RevaultTransactionChain::from_descriptors(
+            deposit_outpoint,
+            deposit_value,
+            &self.deposit_descriptor,
+            &self.unvault_descriptor,
+            &self.cpfp_descriptor,
+            self.child_number,
+            self.emergency_address.clone(),
+            &self.secp,
+        )
+    }
+}
+
+/// The vault used across unit tests: 3 stakeholders, 2 managers and a CSV of 144, drawn from
+/// a fixed seed.
+pub fn test_vault() -> TestVault {
+    TestVault::new(3, 2, 144, &mut fastrand::Rng::with_seed(0)).expect("Valid vault parameters")
+}
+
+/// Create the transaction chain of a vault between `n_stk` stakeholders (each with a cosigning
+/// server) and `n_man` managers. The keys, the derivation index and the deposit outpoint are
+/// drawn from `rng`.
+pub fn build_test_vault(
+    n_stk: usize,
+    n_man: usize,
+    csv: u32,
+    deposit_value: Amount,
+    rng: &mut fastrand::Rng,
+) -> Result<RevaultTransactionChain, Error> {
+    let vault = TestVault::new(n_stk, n_man, csv, rng)?;
+
+    let txid_bytes: Vec<u8> = repeat_with(|| rng.u8(..)).take(32).collect();
+    let deposit_outpoint = OutPoint {
+        txid: Txid::from_slice(&txid_bytes).expect("32 bytes"),
+        vout: rng.u32(..),
+    };
+
+    vault.chain(deposit_outpoint, deposit_value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::build_test_vault;
+
+    use miniscript::bitcoin::{blockdata::constants::COIN_VALUE, Amount};
+
+    #[test]
+    fn test_vault_reproducible() {
+        // The test vault fixture is reproducible given the same RNG seed
+        let seed = fastrand::u64(..);
+        let vault_a = build_test_vault(
+            4,
+            2,
+            144,
+            Amount::from_sat(COIN_VALUE),
+            &mut fastrand::Rng::with_seed(seed),
+        )
+        .expect("Test vault with 4 stakeholders and 2 managers");
+        let vault_b = build_test_vault(
+            4,
+            2,
+            144,
+            Amount::from_sat(COIN_VALUE),
+            &mut fastrand::Rng::with_seed(seed),
+        )
+        .expect("Test vault with 4 stakeholders and 2 managers");
+        assert!(vault_a.iter().eq(vault_b.iter()));
+        assert_eq!(vault_a.vault_value(), Amount::from_sat(COIN_VALUE));
+
+        build_test_vault(
+            2,
+            1,
+            144,
+            Amount::from_sat(1_000),
+            &mut fastrand::Rng::with_seed(seed),
+        )
+        .expect_err("Dust deposit");
+    }
+}
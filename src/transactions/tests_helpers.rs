@@ -1,101 +1,32 @@
 use super::{
-    transaction_chain, CancelTransaction, CpfpTransaction, CpfpableTransaction, DepositTransaction,
-    EmergencyAddress, EmergencyTransaction, RevaultPresignedTransaction, RevaultTransaction,
-    SpendTransaction, UnvaultEmergencyTransaction, UnvaultTransaction, CPFP_MIN_CHANGE,
-    DEPOSIT_MIN_SATS,
+    deposit_to_unvault_value, inner_mut, spend_tx_from_deposits_with_cpfp, total_emergency_cost,
+    total_emergency_value, transaction_chain, utils, CancelStore, CancelTransaction,
+    CpfpTransaction, CpfpableTransaction, DepositTransaction, EmergencyAddress,
+    EmergencyTransaction, RevaultPresignedTransaction, RevaultTransaction, RevaultTransactionChain,
+    SignatureBundle, SpendTransaction, UnvaultEmergencyTransaction, UnvaultTransaction,
+    CPFP_MIN_CHANGE, DEPOSIT_MIN_SATS, MAX_STANDARD_P2WSH_SCRIPT_SIZE, MIN_RELAY_FEERATE,
+    UNVAULT_CPFP_VALUE,
 };
 
+use super::test_utils::get_participants_sets;
 use crate::{error::*, scripts::*, txins::*, txouts::*};
 
-use std::{iter::repeat_with, str::FromStr};
+use std::str::FromStr;
 
 use miniscript::{
     bitcoin::{
+        consensus::encode,
+        hashes::Hash,
         secp256k1,
-        util::psbt::PartiallySignedTransaction as Psbt,
+        util::psbt::{self, PartiallySignedTransaction as Psbt},
         util::{bip143::SigHashCache, bip32},
-        Address, Amount, Network, OutPoint, SigHash, SigHashType, Transaction, TxIn, TxOut,
+        Address, Amount, Network, OutPoint, Script, SigHash, SigHashType, Transaction, TxIn, TxOut,
     },
     descriptor::{DescriptorPublicKey, DescriptorXKey, Wildcard},
+    miniscript::limits::SEQUENCE_LOCKTIME_TYPE_FLAG,
     Descriptor, DescriptorTrait, MiniscriptKey,
 };
 
-fn get_random_privkey(rng: &mut fastrand::Rng) -> bip32::ExtendedPrivKey {
-    let rand_bytes: Vec<u8> = repeat_with(|| rng.u8(..)).take(64).collect();
-
-    bip32::ExtendedPrivKey::new_master(Network::Bitcoin, &rand_bytes)
-        .unwrap_or_else(|_| get_random_privkey(rng))
-}
-
-// This generates the master private keys to derive directly from master, so it's
-// [None]<xpub_goes_here>m/* descriptor pubkeys
-fn get_participants_sets(
-    n_stk: usize,
-    n_man: usize,
-    with_cosig_servers: bool,
-    secp: &secp256k1::Secp256k1<secp256k1::All>,
-) -> (
-    (Vec<bip32::ExtendedPrivKey>, Vec<DescriptorPublicKey>),
-    (Vec<bip32::ExtendedPrivKey>, Vec<DescriptorPublicKey>),
-    (Vec<bip32::ExtendedPrivKey>, Vec<DescriptorPublicKey>),
-    (Vec<bip32::ExtendedPrivKey>, Vec<DescriptorPublicKey>),
-) {
-    let mut rng = fastrand::Rng::new();
-
-    let mut managers_priv = Vec::with_capacity(n_man);
-    let mut managers = Vec::with_capacity(n_man);
-    let mut mancpfp_priv = Vec::with_capacity(n_man);
-    let mut mancpfp = Vec::with_capacity(n_man);
-    for i in 0..n_man {
-        managers_priv.push(get_random_privkey(&mut rng));
-        managers.push(DescriptorPublicKey::XPub(DescriptorXKey {
-            origin: None,
-            xkey: bip32::ExtendedPubKey::from_private(&secp, &managers_priv[i]),
-            derivation_path: bip32::DerivationPath::from(vec![]),
-            wildcard: Wildcard::Unhardened,
-        }));
-
-        mancpfp_priv.push(get_random_privkey(&mut rng));
-        mancpfp.push(DescriptorPublicKey::XPub(DescriptorXKey {
-            origin: None,
-            xkey: bip32::ExtendedPubKey::from_private(&secp, &mancpfp_priv[i]),
-            derivation_path: bip32::DerivationPath::from(vec![]),
-            wildcard: Wildcard::Unhardened,
-        }));
-    }
-
-    let mut stakeholders_priv = Vec::with_capacity(n_stk);
-    let mut stakeholders = Vec::with_capacity(n_stk);
-    let mut cosigners_priv = Vec::with_capacity(n_stk);
-    let mut cosigners = Vec::with_capacity(n_stk);
-    for i in 0..n_stk {
-        stakeholders_priv.push(get_random_privkey(&mut rng));
-        stakeholders.push(DescriptorPublicKey::XPub(DescriptorXKey {
-            origin: None,
-            xkey: bip32::ExtendedPubKey::from_private(&secp, &stakeholders_priv[i]),
-            derivation_path: bip32::DerivationPath::from(vec![]),
-            wildcard: Wildcard::Unhardened,
-        }));
-
-        if with_cosig_servers {
-            cosigners_priv.push(get_random_privkey(&mut rng));
-            cosigners.push(DescriptorPublicKey::XPub(DescriptorXKey {
-                origin: None,
-                xkey: bip32::ExtendedPubKey::from_private(&secp, &cosigners_priv[i]),
-                derivation_path: bip32::DerivationPath::from(vec![]),
-                wildcard: Wildcard::Unhardened,
-            }));
-        }
-    }
-
-    (
-        (managers_priv, managers),
-        (mancpfp_priv, mancpfp),
-        (stakeholders_priv, stakeholders),
-        (cosigners_priv, cosigners),
-    )
-}
-
 // Routine for ""signing"" a transaction
 fn satisfy_transaction_input(
     secp: &secp256k1::Secp256k1<secp256k1::All>,
@@ -189,6 +120,25 @@ fn desc_san_check<P: MiniscriptKey>(desc: &Descriptor<P>) -> Result<(), ScriptCr
     }
 }
 
+// Get the high-S twin of this (low-S) signature, by replacing S with n - S.
+fn high_s_signature(sig: &secp256k1::Signature) -> secp256k1::Signature {
+    const CURVE_ORDER: [u8; 32] = [
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xfe, 0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36,
+        0x41, 0x41,
+    ];
+    let mut compact = sig.serialize_compact();
+
+    let mut borrow = 0;
+    for i in (0..32).rev() {
+        let diff = i16::from(CURVE_ORDER[i]) - i16::from(compact[32 + i]) - borrow;
+        borrow = if diff < 0 { 1 } else { 0 };
+        compact[32 + i] = (diff + 256 * borrow) as u8;
+    }
+
+    secp256k1::Signature::from_compact(&compact).expect("S is still less than n")
+}
+
 macro_rules! roundtrip {
     ($tx:ident, $tx_type:ident) => {
         #[cfg(feature = "use-serde")]
@@ -198,6 +148,7 @@ macro_rules! roundtrip {
             assert_eq!($tx, deserialized_tx);
         }
 
+        assert_eq!($tx.psbt_serialized_len(), $tx.as_psbt_serialized().len());
         let serialized_tx = $tx.to_string();
         let deserialized_tx: $tx_type = FromStr::from_str(&serialized_tx).unwrap();
         assert_eq!($tx, deserialized_tx);
@@ -225,7 +176,13 @@ pub fn derive_transactions(
         (mancpfp_priv, mancpfp),
         (stakeholders_priv, stakeholders),
         (cosigners_priv, cosigners),
-    ) = get_participants_sets(n_stk, n_man, with_cosig_servers, secp);
+    ) = get_participants_sets(
+        n_stk,
+        n_man,
+        with_cosig_servers,
+        &mut fastrand::Rng::new(),
+        secp,
+    );
 
     // Get the script descriptors for the txos we're going to create
     let unvault_descriptor = UnvaultDescriptor::new(
@@ -236,6 +193,14 @@ pub fn derive_transactions(
         csv,
     )?;
     assert_eq!(unvault_descriptor.csv_value(), csv);
+    // Another vault configuration (with a different CSV), for negative tests
+    let other_unvault_descriptor = UnvaultDescriptor::new(
+        stakeholders.clone(),
+        managers.clone(),
+        managers.len(),
+        cosigners.clone(),
+        if csv == 1 { 2 } else { csv ^ 1 },
+    )?;
     let cpfp_descriptor = CpfpDescriptor::new(mancpfp)?;
     let deposit_descriptor = DepositDescriptor::new(stakeholders)?;
 
@@ -295,7 +260,7 @@ pub fn derive_transactions(
     let deposit_txin = DepositTxIn::new(deposit_outpoint, deposit_txo.clone());
 
     // Test that the transaction helper(s) derive the same transactions as we do
-    let (h_unvault, h_cancel, h_emer, h_unemer) = transaction_chain(
+    let chain = RevaultTransactionChain::from_descriptors(
         deposit_outpoint,
         Amount::from_sat(deposit_txo.txout().value),
         &deposit_descriptor,
@@ -305,11 +270,151 @@ pub fn derive_transactions(
         emergency_address.clone(),
         secp,
     )?;
+    assert_eq!(chain.iter().count(), 8);
+    let (chain_unvault, chain_cancel_batch, chain_emergency, chain_unvault_emergency) =
+        transaction_chain(
+            deposit_outpoint,
+            Amount::from_sat(deposit_txo.txout().value),
+            &deposit_descriptor,
+            &unvault_descriptor,
+            &cpfp_descriptor,
+            child_number,
+            emergency_address.clone(),
+            secp,
+        )?;
+    assert_eq!(&chain_unvault, chain.unvault());
+    assert_eq!(
+        chain_cancel_batch.feerate_1000(),
+        chain.cancel_batch().feerate_1000()
+    );
+    assert_eq!(&chain_emergency, chain.emergency());
+    assert_eq!(&chain_unvault_emergency, chain.unvault_emergency());
+    assert_eq!(chain.lock_times_consistent(), Ok(()));
+    let mut locked_emer_psbt = chain.emergency().psbt().clone();
+    locked_emer_psbt.global.unsigned_tx.lock_time = 1;
+    let locked_emer_txid = locked_emer_psbt.global.unsigned_tx.txid();
+    let locked_emer =
+        EmergencyTransaction::from_raw_psbt(&encode::serialize(&locked_emer_psbt)).unwrap();
+    assert_eq!(
+        RevaultTransactionChain::new(
+            chain.unvault().clone(),
+            chain.cancel_batch().clone(),
+            locked_emer,
+            chain.unvault_emergency().clone(),
+        )
+        .unwrap()
+        .lock_times_consistent(),
+        Err(PsbtValidationError::InconsistentLockTime(
+            locked_emer_txid,
+            1
+        ))
+    );
+    assert!(chain
+        .iter()
+        .eq(chain.clone().into_iter().collect::<Vec<_>>().iter()));
+    let raw_chain = chain.serialize();
+    assert!(RevaultTransactionChain::deserialize(&raw_chain)?
+        .iter()
+        .eq(chain.iter()));
+    assert!(matches!(
+        RevaultTransactionChain::deserialize(&raw_chain[..raw_chain.len() - 1]),
+        Err(Error::TransactionSerialisation(
+            TransactionSerialisationError::Encode(_)
+        ))
+    ));
+    let raw_psbts: Vec<Vec<u8>> = chain.iter().skip(1).map(encode::serialize).collect();
+    assert!(matches!(
+        RevaultTransactionChain::deserialize(&encode::serialize(&raw_psbts)),
+        Err(Error::TransactionSerialisation(
+            TransactionSerialisationError::Encode(_)
+        ))
+    ));
+    let (h_unvault, h_cancel, h_emer, h_unemer) = (
+        chain.unvault().clone(),
+        chain.cancel_batch().clone(),
+        chain.emergency().clone(),
+        chain.unvault_emergency().clone(),
+    );
+    let mut cancel_store = CancelStore::new();
+    cancel_store.insert_batch(h_cancel.clone());
+    let unvault_outpoint = h_cancel.feerate_20().tx().input[0].previous_output;
+    assert_eq!(
+        cancel_store.best_cancel_for_feerate(&unvault_outpoint, 0),
+        Some(h_cancel.feerate_20())
+    );
+    assert_eq!(
+        cancel_store.best_cancel_for_feerate(&unvault_outpoint, 101),
+        Some(h_cancel.feerate_200())
+    );
+    assert_eq!(
+        cancel_store.best_cancel_for_feerate(&unvault_outpoint, 1_000),
+        Some(h_cancel.feerate_1000())
+    );
+    assert!(cancel_store
+        .best_cancel_for_feerate(&unvault_outpoint, 1_001)
+        .is_none());
+    assert!(cancel_store
+        .best_cancel_for_feerate(&deposit_outpoint, 0)
+        .is_none());
+
+    RevaultTransactionChain::new(
+        h_unvault.clone(),
+        h_cancel.clone(),
+        h_emer.clone(),
+        h_unemer.clone(),
+    )?;
+    // The Emergency transactions of a chain must pay to the same address
+    let other_emer_address = EmergencyAddress::from(Address::p2wsh(
+        &der_unvault_descriptor.inner().explicit_script(),
+        Network::Bitcoin,
+    ))?;
+    assert!(matches!(
+        RevaultTransactionChain::new(
+            h_unvault.clone(),
+            h_cancel.clone(),
+            EmergencyTransaction::new(deposit_txin.clone(), other_emer_address)?,
+            h_unemer.clone(),
+        ),
+        Err(Error::MismatchedEmergencyAddress)
+    ));
+
+    // A witness Script must be standard
+    assert_eq!(
+        utils::check_witness_script_size(&Script::from(vec![0; MAX_STANDARD_P2WSH_SCRIPT_SIZE])),
+        Ok(())
+    );
+    assert_eq!(
+        utils::check_witness_script_size(&Script::from(vec![
+            0;
+            MAX_STANDARD_P2WSH_SCRIPT_SIZE + 1
+        ])),
+        Err(TransactionCreationError::ScriptTooLarge(
+            MAX_STANDARD_P2WSH_SCRIPT_SIZE + 1
+        ))
+    );
 
     // Create and sign the first (deposit) emergency transaction
     let mut emergency_tx =
         EmergencyTransaction::new(deposit_txin.clone(), emergency_address.clone())?;
     assert_eq!(h_emer, emergency_tx);
+    assert_eq!(
+        EmergencyAddress::from_script(&emergency_tx.tx().output[0].script_pubkey, Network::Bitcoin)
+            .as_ref(),
+        Ok(&emergency_address)
+    );
+    assert_eq!(
+        EmergencyAddress::from_script(&Script::new(), Network::Bitcoin),
+        Err(ScriptCreationError::BadParameters)
+    );
+    assert_eq!(emergency_tx.check_standardness(), Ok(()));
+    let mut final_seq_emer_psbt = emergency_tx.psbt().clone();
+    final_seq_emer_psbt.global.unsigned_tx.input[0].sequence = u32::MAX;
+    assert_eq!(
+        EmergencyTransaction::from_raw_psbt(&encode::serialize(&final_seq_emer_psbt))
+            .unwrap()
+            .check_standardness(),
+        Err(vec![StandardnessViolation::NoRbfSignaling])
+    );
     assert_eq!(
         emergency_tx.emergency_outpoint(),
         OutPoint {
@@ -323,6 +428,46 @@ pub fn derive_transactions(
         emergency_tx.fees().as_sat(),
         (376 + deposit_txin.txout().max_sat_weight() as u64) * 250,
     );
+    assert_eq!(
+        emergency_tx.max_weight(),
+        376 + deposit_txin.txout().max_sat_weight() as u64
+    );
+    assert_eq!(
+        emergency_tx.input_weights(),
+        vec![deposit_txin.txout().max_sat_weight() as u64]
+    );
+    assert_eq!(
+        emergency_tx.simulate_feebump(260),
+        Ok(vec![
+            emergency_tx.tx().output[0].value - emergency_tx.max_weight() * 10
+        ])
+    );
+    let fee_summary = emergency_tx.fee_summary();
+    assert_eq!(fee_summary.total_in, Amount::from_sat(deposit_value));
+    assert_eq!(
+        fee_summary.total_out + fee_summary.absolute_fee,
+        fee_summary.total_in
+    );
+    assert_eq!(fee_summary.absolute_fee, emergency_tx.fees());
+    assert_eq!(fee_summary.vsize, emergency_tx.max_vsize());
+    // 250 sats/WU is 1000 sats/vbyte, modulo the rounding up of the vsize
+    assert!(fee_summary.feerate_vb <= 1_000 && fee_summary.feerate_vb >= 990);
+    assert!(emergency_tx.meets_min_relay_feerate(MIN_RELAY_FEERATE));
+    assert!(emergency_tx.meets_min_relay_feerate(fee_summary.feerate_vb));
+    assert!(!emergency_tx.meets_min_relay_feerate(fee_summary.feerate_vb + 1));
+    assert_eq!(
+        total_emergency_cost(&[emergency_tx.clone(), h_emer.clone()], 10),
+        Ok(emergency_tx.max_vsize() * 2 * 10)
+    );
+    assert_eq!(
+        total_emergency_cost(&[emergency_tx.clone(), h_emer.clone()], u64::MAX / 2),
+        Err(TransactionCreationError::FeerateTooHigh)
+    );
+    assert_eq!(
+        total_emergency_value(&[emergency_tx.clone(), h_emer.clone()]),
+        Ok(emergency_tx.tx().output[0].value * 2)
+    );
+    assert_eq!(total_emergency_value(&[]), Ok(0));
     // We cannot get a sighash for a non-existing input
     assert_eq!(
         emergency_tx.signature_hash(10),
@@ -340,8 +485,69 @@ pub fn derive_transactions(
         child_number,
     )?;
     roundtrip!(emergency_tx, EmergencyTransaction);
+    assert!(emergency_tx.input_final_script_witness(0).is_none());
+    // The version 2 PSBT doesn't contain the global unsigned transaction
+    let psbt_v2 = emergency_tx.as_psbt_v2_serialized();
+    assert_eq!(&psbt_v2[..5], b"psbt\xff");
+    assert!(encode::deserialize::<Psbt>(&psbt_v2).is_err());
+    // The deposit txin signals for RBF
+    assert!(emergency_tx.signals_rbf());
+    assert!(matches!(
+        emergency_tx.serialize_with_txid(),
+        Err(Error::NotFinalized)
+    ));
     emergency_tx.finalize(&secp)?;
     roundtrip!(emergency_tx, EmergencyTransaction);
+    // The length is computed from the PSBT fields, including the ones we never set
+    let mut exotic_psbt = emergency_tx.psbt().clone();
+    exotic_psbt.global.version = 1;
+    exotic_psbt.global.unknown.insert(
+        psbt::raw::Key {
+            type_value: 0x42,
+            key: vec![0x01; 300],
+        },
+        vec![0x02; 70_000],
+    );
+    exotic_psbt.inputs[0].sha256_preimages.insert(
+        miniscript::bitcoin::hashes::sha256::Hash::hash(&[0x03]),
+        vec![0x03],
+    );
+    exotic_psbt.inputs[0].proprietary.insert(
+        psbt::raw::ProprietaryKey {
+            prefix: b"revault".to_vec(),
+            subtype: 0x00,
+            key: vec![0x04; 10],
+        },
+        vec![0x05; 253],
+    );
+    exotic_psbt.inputs[0].non_witness_utxo = Some(emergency_tx.clone().into_tx());
+    exotic_psbt.outputs[0].redeem_script = Some(Script::from(vec![0x06; 0xfd]));
+    assert_eq!(
+        utils::psbt_serialized_len(&exotic_psbt),
+        encode::serialize(&exotic_psbt).len()
+    );
+    assert_eq!(
+        emergency_tx.serialize_with_txid()?,
+        (
+            emergency_tx.clone().into_bitcoin_serialized(),
+            emergency_tx.txid()
+        )
+    );
+    assert_eq!(
+        emergency_tx.input_final_script_witness(0),
+        Some(&emergency_tx.clone().into_tx().input[0].witness)
+    );
+    assert!(emergency_tx.input_final_script_witness(1).is_none());
+    // Once finalized, the weight of the input is the one of its serialized witness
+    assert_eq!(
+        emergency_tx.input_weights(),
+        vec![encode::serialize(emergency_tx.input_final_script_witness(0).unwrap()).len() as u64]
+    );
+    // We can't finalize it twice
+    assert!(matches!(
+        emergency_tx.finalize(secp),
+        Err(Error::AlreadyFinalized)
+    ));
 
     // Create but don't sign the unvaulting transaction until all revaulting transactions
     // are finalized
@@ -354,7 +560,83 @@ pub fn derive_transactions(
     roundtrip!(unvault_tx, UnvaultTransaction);
 
     assert_eq!(h_unvault, unvault_tx);
+    // The deposit may be encumbered by a relative timelock, which only changes the nSequence
+    let csv_deposit_txin = DepositTxIn::new_with_csv(deposit_outpoint, deposit_txo.clone(), csv);
+    assert_eq!(deposit_txin.csv(), Some(0));
+    assert_eq!(csv_deposit_txin.csv(), Some(csv));
+    // A time-based relative timelock is not expressed in blocks
+    let time_csv_deposit_txin = DepositTxIn::new_with_csv(
+        deposit_outpoint,
+        deposit_txo.clone(),
+        SEQUENCE_LOCKTIME_TYPE_FLAG | csv,
+    );
+    assert_eq!(time_csv_deposit_txin.csv(), None);
+    let time_csv_unvault_tx = UnvaultTransaction::new(
+        time_csv_deposit_txin,
+        &der_unvault_descriptor,
+        &der_cpfp_descriptor,
+    )?;
+    assert!(!time_csv_unvault_tx.is_spendable_at(&[0], u32::MAX));
+    let csv_unvault_tx = UnvaultTransaction::new(
+        csv_deposit_txin.clone(),
+        &der_unvault_descriptor,
+        &der_cpfp_descriptor,
+    )?;
+    let csv_emergency_tx =
+        EmergencyTransaction::new(csv_deposit_txin.clone(), emergency_address.clone())?;
+    assert_eq!(csv_unvault_tx.tx().input[0].sequence, csv);
+    assert_eq!(csv_emergency_tx.tx().input[0].sequence, csv);
+    assert_eq!(csv_unvault_tx.fees(), unvault_tx.fees());
+    assert_eq!(csv_unvault_tx.tx().output, unvault_tx.tx().output);
+    assert_eq!(unvault_tx.check_cpfp_txout(&der_cpfp_descriptor), Ok(()));
+    assert_eq!(
+        unvault_tx
+            .check_cpfp_txout(&cpfp_descriptor.derive(other_child_number(child_number), secp)),
+        Err(PsbtValidationError::InvalidCpfpOutput)
+    );
     let unvault_value = unvault_tx.psbt().global.unsigned_tx.output[0].value;
+    assert_eq!(unvault_tx.validate_unvault_value(deposit_value), Ok(()));
+    assert_eq!(
+        unvault_tx.validate_unvault_value(deposit_value - 1),
+        Err(PsbtValidationError::InvalidUnvaultValue(unvault_value - 1))
+    );
+    assert_eq!(
+        unvault_tx.validate_unvault_value(UNVAULT_CPFP_VALUE),
+        Err(PsbtValidationError::InsaneAmounts)
+    );
+    let mut inflated_unvault_psbt = unvault_tx.psbt().clone();
+    inflated_unvault_psbt.global.unsigned_tx.output[0].value += 1;
+    let inflated_unvault_tx =
+        UnvaultTransaction::from_raw_psbt(&encode::serialize(&inflated_unvault_psbt)).unwrap();
+    assert_eq!(
+        inflated_unvault_tx.validate_unvault_value(deposit_value),
+        Err(PsbtValidationError::InvalidUnvaultValue(unvault_value))
+    );
+    let mut no_witscript_psbt = unvault_tx.psbt().clone();
+    no_witscript_psbt.inputs[0].witness_script = None;
+    assert_eq!(
+        utils::estimated_input_weights(&no_witscript_psbt),
+        Err(PsbtValidationError::MissingInWitnessScript(
+            no_witscript_psbt.inputs[0].clone()
+        ))
+    );
+    let mut bad_witscript_psbt = unvault_tx.psbt().clone();
+    bad_witscript_psbt.inputs[0].witness_script = Some(Script::from(vec![0xff]));
+    assert_eq!(
+        utils::estimated_input_weights(&bad_witscript_psbt),
+        Err(PsbtValidationError::InvalidInWitnessScript(
+            bad_witscript_psbt.inputs[0].clone()
+        ))
+    );
+    assert_eq!(
+        deposit_to_unvault_value(
+            Amount::from_sat(deposit_value),
+            &der_deposit_descriptor,
+            &der_unvault_descriptor,
+            &der_cpfp_descriptor
+        ),
+        Ok(Amount::from_sat(unvault_value))
+    );
     // 548 is the witstrip weight of an unvault tx (1 segwit input, 2 P2WSH txouts), 6 is the
     // feerate is sat/WU, and 30_000 is the CPFP output value.
     assert_eq!(
@@ -364,6 +646,22 @@ pub fn derive_transactions(
 
     // Create and sign the cancel transaction
     let rev_unvault_txin = unvault_tx.revault_unvault_txin(&der_unvault_descriptor);
+    assert_eq!(rev_unvault_txin.sequence(), RBF_SEQUENCE);
+    assert_eq!(rev_unvault_txin.csv(), Some(0));
+    assert_eq!(
+        unvault_tx.unvault_address(&der_unvault_descriptor, Network::Testnet),
+        Some(Address::p2wsh(
+            &der_unvault_descriptor.inner().explicit_script(),
+            Network::Testnet
+        ))
+    );
+    assert_eq!(
+        unvault_tx.unvault_address(
+            &unvault_descriptor.derive(other_child_number(child_number), secp),
+            Network::Testnet
+        ),
+        None
+    );
     assert_eq!(rev_unvault_txin.txout().txout().value, unvault_value);
     let mut cancel_tx = CancelTransaction::new(
         rev_unvault_txin.clone(),
@@ -372,6 +670,69 @@ pub fn derive_transactions(
     )?;
     roundtrip!(cancel_tx, CancelTransaction);
     assert_eq!(h_cancel.feerate_200(), &cancel_tx);
+    let other_cancel_tx = CancelTransaction::new(
+        rev_unvault_txin.clone(),
+        &deposit_descriptor.derive(other_child_number(child_number), secp),
+        Amount::from_sat(50),
+    )?;
+    // They all spend the same Unvault output
+    assert!(cancel_tx.conflicts_with(h_cancel.feerate_1000()));
+    assert!(cancel_tx.conflicts_with(&other_cancel_tx));
+    assert!(!cancel_tx.conflicts_with(&cancel_tx.clone()));
+    let csv_unvault_cancel_tx = CancelTransaction::new(
+        csv_unvault_tx.revault_unvault_txin(&der_unvault_descriptor),
+        &der_deposit_descriptor,
+        Amount::from_sat(50),
+    )?;
+    assert!(!cancel_tx.conflicts_with(&csv_unvault_cancel_tx));
+    assert!(cancel_tx.revaults_to(&der_deposit_descriptor));
+    assert!(cancel_tx.is_cancel_of(&unvault_tx));
+    // The revocation transactions don't have to wait for any timelock
+    assert!(cancel_tx.is_spendable_at(&[100], 100));
+    assert!(!cancel_tx.is_cancel_of(&csv_unvault_tx));
+    assert_eq!((cancel_tx.num_inputs(), cancel_tx.num_outputs()), (1, 1));
+    assert_eq!(
+        cancel_tx.output_witness_programs(),
+        vec![Some((
+            0,
+            der_deposit_descriptor.inner().script_pubkey().as_bytes()[2..].to_vec()
+        ))]
+    );
+    assert!(!other_cancel_tx.revaults_to(&der_deposit_descriptor));
+    assert_eq!(cancel_tx.check_input_amounts(&[unvault_value]), Ok(()));
+    assert_eq!(
+        cancel_tx.check_input_amounts(&[unvault_value + 1]),
+        Err(PsbtValidationError::InputAmountMismatch(
+            0,
+            unvault_value + 1,
+            unvault_value
+        ))
+    );
+    assert_eq!(
+        cancel_tx.check_input_amounts(&[unvault_value, unvault_value]),
+        Err(PsbtValidationError::InvalidInputCount(1))
+    );
+    let unvault_prevout = (
+        rev_unvault_txin.outpoint(),
+        rev_unvault_txin.txout().txout().clone(),
+    );
+    let mut lied_prevout = unvault_prevout.clone();
+    lied_prevout.1.value += 1;
+    assert_eq!(
+        cancel_tx.verify_prevouts(&[lied_prevout]),
+        Err(PsbtValidationError::PrevoutMismatch(0))
+    );
+    let mut other_prevout = unvault_prevout.clone();
+    other_prevout.0.vout ^= 1;
+    assert_eq!(
+        cancel_tx.verify_prevouts(&[other_prevout]),
+        Err(PsbtValidationError::PrevoutMismatch(0))
+    );
+    assert_eq!(cancel_tx.verify_prevouts(&[unvault_prevout]), Ok(()));
+    assert_eq!(
+        cancel_tx.verify_prevouts(&[]),
+        Err(PsbtValidationError::InvalidInputCount(1))
+    );
     assert_eq!(
         cancel_tx.deposit_txin(&der_deposit_descriptor).outpoint(),
         OutPoint {
@@ -384,8 +745,38 @@ pub fn derive_transactions(
         cancel_tx.fees().as_sat(),
         (376 + rev_unvault_txin.txout().max_sat_weight() as u64) * 50,
     );
+    assert_eq!(
+        cancel_tx.check_fees(&rev_unvault_txin, Amount::from_sat(50)),
+        Ok(())
+    );
+    assert_eq!(
+        cancel_tx.check_fees(&rev_unvault_txin, Amount::from_sat(49)),
+        Err(PsbtValidationError::InvalidFeeAmount(
+            (376 + rev_unvault_txin.txout().max_sat_weight() as u64) * 49,
+            cancel_tx.fees().as_sat()
+        ))
+    );
+    // Bumping to a lower feerate doesn't change anything, otherwise the deposit output pays for it
+    let cancel_value = cancel_tx.tx().output[0].value;
+    assert_eq!(cancel_tx.simulate_feebump(10), Ok(vec![cancel_value]));
+    assert_eq!(
+        cancel_tx.simulate_feebump(51),
+        Ok(vec![
+            cancel_value - (376 + rev_unvault_txin.txout().max_sat_weight() as u64)
+        ])
+    );
+    assert_eq!(
+        cancel_tx.simulate_feebump(unvault_value),
+        Err(TransactionCreationError::FeerateTooHigh)
+    );
+    assert_eq!(cancel_tx.tx().output[0].value, cancel_value);
     let cancel_tx_sighash = cancel_tx.sig_hash().expect("Input exists");
+    assert_eq!(
+        cancel_tx.signature_hash_bytes(0).expect("Input exists"),
+        cancel_tx_sighash.into_inner()
+    );
     roundtrip!(cancel_tx, CancelTransaction);
+    let mut unsigned_cancel_tx = cancel_tx.clone();
     satisfy_transaction_input(
         &secp,
         &mut cancel_tx,
@@ -395,8 +786,131 @@ pub fn derive_transactions(
         child_number,
     )?;
     roundtrip!(cancel_tx, CancelTransaction);
+    assert!(unsigned_cancel_tx.signed_by().is_empty());
+    assert_eq!(
+        cancel_tx.signed_by(),
+        cancel_tx.psbt().inputs[0]
+            .partial_sigs
+            .keys()
+            .copied()
+            .collect()
+    );
+    assert_eq!(cancel_tx.signed_by().len(), stakeholders_priv.len());
+    let partial_sigs = cancel_tx.all_partial_sigs();
+    assert_eq!(partial_sigs.len(), stakeholders_priv.len());
+    for (i, pubkey, sig) in partial_sigs {
+        assert_eq!(i, 0);
+        assert_eq!(
+            cancel_tx.psbt().inputs[0].partial_sigs.get(&pubkey),
+            Some(&sig)
+        );
+    }
+    assert!(unsigned_cancel_tx.all_partial_sigs().is_empty());
+
+    cancel_tx.verify_signatures(secp)?;
+    assert!(cancel_tx.all_sigs_low_s());
+    assert!(unsigned_cancel_tx.all_sigs_low_s());
+    // Swapping a signature for its high-S twin is detected
+    let mut high_s_psbt = cancel_tx.psbt().clone();
+    let (high_s_pubkey, rawsig) = high_s_psbt.inputs[0]
+        .partial_sigs
+        .iter_mut()
+        .next()
+        .unwrap();
+    let sig = secp256k1::Signature::from_der(&rawsig[..rawsig.len() - 1]).unwrap();
+    let mut high_s_rawsig = high_s_signature(&sig).serialize_der().to_vec();
+    high_s_rawsig.push(SigHashType::All as u8);
+    *rawsig = high_s_rawsig;
+    let high_s_pubkey = high_s_pubkey.key;
+    let high_s_cancel_tx =
+        CancelTransaction::from_raw_psbt(&encode::serialize(&high_s_psbt)).unwrap();
+    assert!(!high_s_cancel_tx.all_sigs_low_s());
+    assert!(matches!(
+        high_s_cancel_tx.check_sigs_low_s(),
+        Err(Error::InvalidSignature(0, pk)) if pk == high_s_pubkey
+    ));
+    // Adding all the signatures at once gives the same result
+    let sigs: Vec<_> = cancel_tx.psbt().inputs[0]
+        .partial_sigs
+        .iter()
+        .map(|(pubkey, rawsig)| {
+            let der_sig = &rawsig[..rawsig.len() - 1];
+            (pubkey.key, secp256k1::Signature::from_der(der_sig).unwrap())
+        })
+        .collect();
+    let mut batch_signed_cancel_tx = unsigned_cancel_tx.clone();
+    batch_signed_cancel_tx.add_signatures(0, sigs, secp)?;
+    assert_eq!(batch_signed_cancel_tx, cancel_tx);
+    // We can tell why it could not be finalized
+    assert_eq!(
+        unsigned_cancel_tx.diagnose_finalize(),
+        vec![(0, FinalizeDiagnosis::MissingSignature)]
+    );
+    assert!(cancel_tx.diagnose_finalize().is_empty());
+    // A signature for another key is invalid
+    let mut bad_psbt = cancel_tx.clone().into_psbt();
+    let pubkeys: Vec<_> = bad_psbt.inputs[0].partial_sigs.keys().cloned().collect();
+    if pubkeys.len() > 1 {
+        let sig = bad_psbt.inputs[0].partial_sigs[&pubkeys[0]].clone();
+        bad_psbt.inputs[0].partial_sigs.insert(pubkeys[1], sig);
+        let bad_cancel_tx =
+            CancelTransaction::from_raw_psbt(&encode::serialize(&bad_psbt)).unwrap();
+        match bad_cancel_tx.verify_signatures(secp) {
+            Err(Error::InvalidSignature(0, pk)) => assert_eq!(pk, pubkeys[1].key),
+            e => panic!("Unexpected result: {:?}", e),
+        }
+    }
+
+    // The signatures can be exchanged without the rest of the PSBT
+    let sig_bundle = cancel_tx.signature_bundle();
+    assert_eq!(sig_bundle.signatures().len(), stakeholders_priv.len());
+    #[cfg(feature = "use-serde")]
+    {
+        let serialized = serde_json::to_string(&sig_bundle).unwrap();
+        assert_eq!(sig_bundle, serde_json::from_str(&serialized).unwrap());
+    }
+    let mut bad_sigs = sig_bundle.signatures().clone();
+    bad_sigs
+        .values_mut()
+        .last()
+        .expect("At least one stakeholder")
+        .pop();
+    assert!(matches!(
+        unsigned_cancel_tx.apply_signature_bundle(&SignatureBundle::new(bad_sigs), secp),
+        Err(Error::InputSatisfaction(
+            InputSatisfactionError::InvalidSignatureEncoding(_)
+        ))
+    ));
+    assert!(unsigned_cancel_tx.signatures().is_empty());
+    unsigned_cancel_tx.apply_signature_bundle(&sig_bundle, secp)?;
+    assert_eq!(unsigned_cancel_tx, cancel_tx);
+    assert!(matches!(
+        cancel_tx.fee_overpayment(),
+        Err(Error::NotFinalized)
+    ));
     cancel_tx.finalize(&secp).unwrap();
     roundtrip!(cancel_tx, CancelTransaction);
+    #[cfg(feature = "verify")]
+    {
+        cancel_tx
+            .verify_input_with_flags(0, bitcoinconsensus::VERIFY_ALL)
+            .unwrap();
+        cancel_tx
+            .verify_input_with_flags(0, bitcoinconsensus::VERIFY_NONE)
+            .unwrap();
+        assert!(matches!(
+            cancel_tx.verify_input_with_flags(1, bitcoinconsensus::VERIFY_ALL),
+            Err(Error::InputSatisfaction(
+                InputSatisfactionError::OutOfBounds
+            ))
+        ));
+    }
+    // The fees were computed for the maximum satisfaction weight at 50 sats/WU
+    let cancel_actual_weight = cancel_tx.clone().into_psbt().extract_tx().get_weight() as u64;
+    assert_eq!(
+        cancel_tx.fee_overpayment().unwrap(),
+        (376 + rev_unvault_txin.txout().max_sat_weight() as u64 - cancel_actual_weight) * 50
+    );
 
     let mut unemergency_tx =
         UnvaultEmergencyTransaction::new(rev_unvault_txin.clone(), emergency_address.clone())?;
@@ -494,22 +1008,42 @@ pub fn derive_transactions(
         );
     }
 
+    assert_eq!(
+        unvault_tx.package_weight(&cpfp_tx),
+        unvault_tx.max_weight() + utils::max_weight(cpfp_tx.psbt())
+    );
+    // The package can't be submitted before the child is finalized
+    assert!(matches!(
+        unvault_tx.cpfp_package(&cpfp_tx, &cpfp_descriptor, secp),
+        Err(Error::NotFinalized)
+    ));
     // we sign the cpfp and then check the package feerate
+    let mut cpfp_tx = cpfp_tx;
     let cpfp_fees = cpfp_tx.fees();
     let inputs_len = cpfp_tx.psbt().inputs.len();
-    let mut psbt = cpfp_tx.into_psbt();
     for i in 0..inputs_len {
-        sign_psbt(&secp, &mut psbt, i, &mancpfp_priv, child_number);
+        sign_psbt(&secp, cpfp_tx.psbt_mut(), i, &mancpfp_priv, child_number);
     }
-    finalize_psbt(&secp, &mut psbt);
+    finalize_psbt(&secp, cpfp_tx.psbt_mut());
+    assert!(cpfp_tx.is_finalized());
     assert!(
         1000 * (cpfp_fees + unvault_tx.fees()).as_sat()
-            / (psbt.global.unsigned_tx.get_weight() as u64 + unvault_tx.max_weight())
+            / (cpfp_tx.tx().get_weight() as u64 + unvault_tx.max_weight())
             >= unvault_tx.max_feerate() * 1000 + added_feerate
     );
+    // The finalized Unvault can be submitted along with its finalized child
+    assert_eq!(
+        unvault_tx.cpfp_package(&cpfp_tx, &cpfp_descriptor, secp)?,
+        vec![
+            encode::serialize_hex(&unvault_tx.clone().into_tx()),
+            encode::serialize_hex(&cpfp_tx.psbt().clone().extract_tx()),
+        ]
+    );
 
     // Create and sign a spend transaction
     let spend_unvault_txin = unvault_tx.spend_unvault_txin(&der_unvault_descriptor);
+    assert_eq!(spend_unvault_txin.sequence(), csv);
+    assert_eq!(spend_unvault_txin.csv(), Some(csv));
     let unvault_value = spend_unvault_txin.txout().txout().value;
     let dummy_txo = TxOut::default();
     let cpfp_value = SpendTransaction::cpfp_txout(
@@ -518,7 +1052,7 @@ pub fn derive_transactions(
         None,
         &der_cpfp_descriptor,
         0,
-    )
+    )?
     .txout()
     .value;
     let change_value = unvault_value
@@ -552,6 +1086,9 @@ pub fn derive_transactions(
             None,
         )
     };
+    let change_amount = change_txo
+        .as_ref()
+        .map(|txo| Amount::from_sat(txo.txout().value));
     let mut spend_tx = SpendTransaction::new(
         vec![spend_unvault_txin.clone()],
         vec![SpendTxOut::new(spend_txo.clone())],
@@ -562,7 +1099,131 @@ pub fn derive_transactions(
     )
     .expect("Amounts ok");
     roundtrip!(spend_tx, SpendTransaction);
+    assert_eq!(
+        spend_tx.change_amount(&der_deposit_descriptor),
+        change_amount
+    );
+    assert_eq!(
+        SpendTransaction::from_reader(spend_tx.as_psbt_serialized().as_slice()).unwrap(),
+        spend_tx
+    );
+    assert_eq!(spend_tx.num_inputs(), 1);
+    assert_eq!(
+        spend_tx.unvault_txins(&unvault_descriptor, secp),
+        Ok(vec![spend_unvault_txin.clone()])
+    );
+    let mut two_inputs_psbt = spend_tx.psbt().clone();
+    two_inputs_psbt
+        .inputs
+        .push(two_inputs_psbt.inputs[0].clone());
+    let mut dup_txin = two_inputs_psbt.global.unsigned_tx.input[0].clone();
+    dup_txin.previous_output.vout += 1;
+    two_inputs_psbt.global.unsigned_tx.input.push(dup_txin);
+    let two_inputs_spend_tx =
+        SpendTransaction::from_raw_psbt(&encode::serialize(&two_inputs_psbt)).unwrap();
+    assert_eq!(
+        two_inputs_spend_tx
+            .all_input_sighashes()?
+            .into_iter()
+            .map(|(_, sighash_type)| sighash_type)
+            .collect::<Vec<_>>(),
+        vec![SigHashType::All, SigHashType::All]
+    );
+    // We never sign with another signature hash type, so we refuse PSBTs specifying one
+    two_inputs_psbt.inputs[1].sighash_type = Some(SigHashType::AllPlusAnyoneCanPay);
+    assert!(matches!(
+        SpendTransaction::from_raw_psbt(&encode::serialize(&two_inputs_psbt)),
+        Err(TransactionSerialisationError::Validation(
+            PsbtValidationError::InvalidSigHashType(_)
+        ))
+    ));
+    // Without its duplicated input, it's the original Spend
+    assert_eq!(
+        two_inputs_spend_tx.feerate_without_input(1)?,
+        spend_tx.max_feerate()
+    );
+    assert!(matches!(
+        spend_tx.feerate_without_input(0),
+        Err(Error::TransactionCreation(
+            TransactionCreationError::NegativeFees
+        ))
+    ));
+    assert!(matches!(
+        spend_tx.feerate_without_input(1),
+        Err(Error::InputSatisfaction(
+            InputSatisfactionError::OutOfBounds
+        ))
+    ));
+    assert_eq!(
+        spend_tx.num_outputs(),
+        if change_amount.is_some() { 3 } else { 2 }
+    );
+    // The CPFP output pays to a P2WSH, the test destinations to an empty Script
+    let spend_programs = spend_tx.output_witness_programs();
+    assert!(matches!(spend_programs[0], Some((0, ref prog)) if prog.len() == 32));
+    assert!(spend_programs.contains(&None));
+    let spend_addresses = spend_tx.output_addresses(Network::Bitcoin);
+    assert_eq!(
+        spend_addresses[0],
+        Some(Address::p2wsh(
+            &der_cpfp_descriptor.inner().explicit_script(),
+            Network::Bitcoin
+        ))
+    );
+    assert!(spend_addresses.contains(&None));
+
+    // Destinations can be added to the Spend as long as it's not signed
+    let mut spend_tx_dest = spend_tx.clone();
+    let dest_addr = Address::p2wsh(
+        &der_deposit_descriptor.inner().explicit_script(),
+        Network::Bitcoin,
+    );
+    assert!(matches!(
+        spend_tx_dest.add_destination(&dest_addr, Amount::from_sat(1), &der_cpfp_descriptor),
+        Err(Error::TransactionCreation(TransactionCreationError::Dust))
+    ));
+    // The Spend pays 10_000 sats of fees, enough for both the new output and the increased
+    // CPFP output value (16 sats per WU of the 43 vbytes P2WSH output).
+    assert_eq!(spend_tx.fees().as_sat(), fees);
+    spend_tx_dest.add_destination(&dest_addr, Amount::from_sat(1_000), &der_cpfp_descriptor)?;
+    assert_eq!(
+        spend_tx_dest.tx().output.len(),
+        spend_tx.tx().output.len() + 1
+    );
+    assert_eq!(
+        spend_tx_dest.psbt().outputs.len(),
+        spend_tx_dest.tx().output.len()
+    );
+    assert_eq!(
+        spend_tx_dest.tx().output[0].value,
+        16 * spend_tx_dest.max_weight()
+    );
+    assert_eq!(
+        spend_tx_dest.tx().output[0].value,
+        spend_tx.tx().output[0].value + 16 * P2WSH_TXO_WEIGHT
+    );
+    assert_eq!(
+        spend_tx_dest.fees().as_sat(),
+        fees - 1_000 - 16 * P2WSH_TXO_WEIGHT
+    );
+    roundtrip!(spend_tx_dest, SpendTransaction);
+    // Now it can't afford another output of the value of its remaining fees
+    let spend_tx_no_dest = spend_tx_dest.clone();
+    assert!(matches!(
+        spend_tx_dest.add_destination(&dest_addr, spend_tx_dest.fees(), &der_cpfp_descriptor),
+        Err(Error::TransactionCreation(
+            TransactionCreationError::NegativeFees
+        ))
+    ));
+    assert_eq!(spend_tx_dest, spend_tx_no_dest);
+
     let spend_tx_sighash = spend_tx.signature_hash(0).expect("Input exists");
+    let all_sighashes = spend_tx.all_input_sighashes()?;
+    assert_eq!(all_sighashes.len(), spend_tx.tx().input.len());
+    for (i, (sighash, sighash_type)) in all_sighashes.into_iter().enumerate() {
+        assert_eq!(sighash, spend_tx.signature_hash(i)?);
+        assert_eq!(sighash_type, SigHashType::All);
+    }
     satisfy_transaction_input(
         &secp,
         &mut spend_tx,
@@ -576,8 +1237,171 @@ pub fn derive_transactions(
         child_number,
     )?;
     roundtrip!(spend_tx, SpendTransaction);
+    assert!(matches!(
+        spend_tx
+            .clone()
+            .add_destination(&dest_addr, Amount::from_sat(1_000), &der_cpfp_descriptor),
+        Err(Error::AlreadySigned)
+    ));
+    assert!(spend_tx.diagnose_finalize().is_empty());
+    if csv > 0 {
+        let mut early_spend_tx = spend_tx.clone();
+        inner_mut::PrivateInnerMut::psbt_mut(&mut early_spend_tx)
+            .global
+            .unsigned_tx
+            .input[0]
+            .sequence = csv - 1;
+        assert_eq!(
+            early_spend_tx.diagnose_finalize(),
+            vec![(0, FinalizeDiagnosis::TimelockNotMet)]
+        );
+    }
     spend_tx.finalize(&secp)?;
     roundtrip!(spend_tx, SpendTransaction);
+    assert!(spend_tx.diagnose_finalize().is_empty());
+    // The Spend feerate is usually not an integer, the overpayment must not be computed from a
+    // rounded-down one.
+    let spend_estimated_weight = spend_tx.tx().get_weight() as u64
+        + utils::estimated_input_weights(spend_tx.psbt())
+            .unwrap()
+            .into_iter()
+            .sum::<u64>();
+    let spend_actual_weight = spend_tx.clone().into_psbt().extract_tx().get_weight() as u64;
+    assert_eq!(
+        spend_tx.fee_overpayment().unwrap(),
+        fees * (spend_estimated_weight - spend_actual_weight) / spend_estimated_weight
+    );
+    assert_eq!(
+        spend_tx.unvault_txins(&unvault_descriptor, secp),
+        Err(PsbtValidationError::FinalizedInput(0))
+    );
+    assert_eq!(
+        spend_tx.check_unvault_descriptor(&unvault_descriptor, secp),
+        Err(PsbtValidationError::FinalizedInput(0))
+    );
+
+    // The Spend CPFP output may be controlled by different keys than the Unvault CPFP output
+    let spend_cpfp_descriptor = CpfpDescriptor::new(managers.clone())?;
+    let spend_tx = spend_tx_from_deposits_with_cpfp(
+        vec![(
+            deposit_outpoint,
+            Amount::from_sat(deposit_value),
+            child_number,
+        )],
+        vec![SpendTxOut::new(spend_txo.clone())],
+        None,
+        &deposit_descriptor,
+        &unvault_descriptor,
+        &cpfp_descriptor,
+        &spend_cpfp_descriptor,
+        0,
+        false,
+        secp,
+    )?;
+    assert_eq!(
+        spend_tx.tx().output[0].script_pubkey,
+        spend_cpfp_descriptor
+            .derive(child_number, secp)
+            .inner()
+            .script_pubkey()
+    );
+    assert!(spend_tx.cpfp_txin(&cpfp_descriptor, secp).is_none());
+    assert!(spend_tx.cpfp_txin(&spend_cpfp_descriptor, secp).is_some());
+    assert_eq!(
+        spend_tx.check_unvault_descriptor(&unvault_descriptor, secp),
+        Ok(())
+    );
+    let der_spend_cpfp_descriptor = spend_cpfp_descriptor.derive(child_number, secp);
+    assert_eq!(
+        spend_tx.check_cpfp_txout(&der_spend_cpfp_descriptor),
+        Ok(())
+    );
+    assert_eq!(
+        spend_tx.check_cpfp_txout(&der_cpfp_descriptor),
+        Err(PsbtValidationError::CpfpNotFirst)
+    );
+    assert_eq!(
+        spend_tx.check_unvault_descriptor(&other_unvault_descriptor, secp),
+        Err(PsbtValidationError::MixedUnvaultDescriptors)
+    );
+    assert_eq!(
+        spend_tx.unvault_txins(&other_unvault_descriptor, secp),
+        Err(PsbtValidationError::MixedUnvaultDescriptors)
+    );
+
+    // We can detect outputs paying to a non-standard Script, such as our (empty) test
+    // destination Script
+    assert!(spend_tx.has_nonstandard_output());
+    assert_eq!(
+        spend_tx.check_outputs_standard(),
+        Err(PsbtValidationError::NonStandardOutput(1))
+    );
+    let standard_spend_tx = SpendTransaction::new(
+        vec![spend_unvault_txin.clone()],
+        vec![SpendTxOut::new(TxOut {
+            value: spend_txo.value,
+            script_pubkey: emergency_address.address().script_pubkey(),
+        })],
+        None,
+        &der_cpfp_descriptor,
+        0,
+        false,
+    )?;
+    assert!(!standard_spend_tx.has_nonstandard_output());
+    assert_eq!(standard_spend_tx.check_outputs_standard(), Ok(()));
+    assert_eq!(
+        spend_tx.check_standardness(),
+        Err(vec![StandardnessViolation::NonStandardOutput(1)])
+    );
+    assert_eq!(standard_spend_tx.check_standardness(), Ok(()));
+
+    // A Spend may contain a single null-data output
+    let op_return_txo = SpendTxOut::op_return(b"audit tag").unwrap();
+    assert_eq!(
+        SpendTxOut::op_return(&[0; MAX_OP_RETURN_DATA + 1]),
+        Err(TxoutCreationError::OpReturnTooLarge(MAX_OP_RETURN_DATA + 1))
+    );
+    let standard_dest_txo = SpendTxOut::new(TxOut {
+        value: spend_txo.value,
+        script_pubkey: emergency_address.address().script_pubkey(),
+    });
+    let op_return_spend_tx = SpendTransaction::new(
+        vec![spend_unvault_txin.clone()],
+        vec![standard_dest_txo.clone(), op_return_txo.clone()],
+        None,
+        &der_cpfp_descriptor,
+        0,
+        false,
+    )?;
+    roundtrip!(op_return_spend_tx, SpendTransaction);
+    assert_eq!(op_return_spend_tx.check_outputs_standard(), Ok(()));
+    assert_eq!(
+        SpendTransaction::new(
+            vec![spend_unvault_txin.clone()],
+            vec![standard_dest_txo, op_return_txo.clone(), op_return_txo],
+            None,
+            &der_cpfp_descriptor,
+            0,
+            false,
+        ),
+        Err(TransactionCreationError::MultipleOpReturn)
+    );
+    let mut p2tr_spk = vec![0x51, 0x20];
+    p2tr_spk.extend_from_slice(&[0x42; 32]);
+    assert!(utils::is_standard_script_pubkey(&Script::from(p2tr_spk)));
+    // A null-data output may only contain pushes after the OP_RETURN
+    assert!(utils::is_standard_script_pubkey(
+        &SpendTxOut::op_return(b"audit tag")
+            .unwrap()
+            .txout()
+            .script_pubkey
+    ));
+    assert!(!utils::is_standard_script_pubkey(&Script::from(vec![
+        0x6a, 0x01, 0x42, 0xac
+    ])));
+    assert!(utils::is_standard_script_pubkey(&Script::from(vec![
+        0x6a, 0x01, 0x42, 0x60
+    ])));
 
     // We can't create a dust output with the Spend
     let dust_txo = TxOut {
@@ -624,6 +1448,33 @@ pub fn derive_transactions(
             )
         })
         .collect();
+    // A pathologically large batch is refused, not a panic
+    let huge_batch: Vec<UnvaultTxIn> = (0..1_500)
+        .map(|vout| {
+            UnvaultTxIn::new(
+                OutPoint {
+                    txid: spend_unvault_txins[0].outpoint().txid,
+                    vout,
+                },
+                spend_unvault_txins[0].txout().clone(),
+                csv,
+            )
+        })
+        .collect();
+    assert_eq!(
+        SpendTransaction::new(
+            huge_batch,
+            vec![SpendTxOut::new(TxOut {
+                value: 1_000_000,
+                ..TxOut::default()
+            })],
+            None,
+            &der_cpfp_descriptor,
+            0,
+            true,
+        ),
+        Err(TransactionCreationError::TooLarge)
+    );
     let n_txins = spend_unvault_txins.len();
     let dummy_txo = TxOut::default();
     let cpfp_value = SpendTransaction::cpfp_txout(
@@ -632,7 +1483,7 @@ pub fn derive_transactions(
         None,
         &der_cpfp_descriptor,
         0,
-    )
+    )?
     .txout()
     .value;
     let fees = 30_000;
@@ -660,6 +1511,10 @@ pub fn derive_transactions(
     )?;
     roundtrip!(spend_tx, SpendTransaction);
     assert_eq!(spend_tx.fees().as_sat(), fees);
+    let conf_heights = vec![100; n_txins];
+    assert!(spend_tx.is_spendable_at(&conf_heights, 100 + csv));
+    assert!(!spend_tx.is_spendable_at(&conf_heights, 100 + csv - 1));
+    assert!(!spend_tx.is_spendable_at(&conf_heights[1..], 100 + csv));
     let mut hash_cache = SigHashCache::new(spend_tx.tx());
     let sighashes: Vec<SigHash> = (0..n_txins)
         .into_iter()
@@ -732,27 +1587,72 @@ pub fn derive_transactions(
         );
     }
 
+    // Neither the Spend nor this CPFP are finalized yet
+    assert!(matches!(
+        spend_tx.cpfp_package(&cpfp_tx, &cpfp_descriptor, secp),
+        Err(Error::NotFinalized)
+    ));
     // we sign the cpfp and then check the package feerate
+    let mut cpfp_tx = cpfp_tx;
     let cpfp_fees = cpfp_tx.fees();
     let inputs_len = cpfp_tx.psbt().inputs.len();
-    let mut psbt = cpfp_tx.into_psbt();
     for i in 0..inputs_len {
-        sign_psbt(&secp, &mut psbt, i, &mancpfp_priv, child_number);
+        sign_psbt(&secp, cpfp_tx.psbt_mut(), i, &mancpfp_priv, child_number);
     }
-    finalize_psbt(&secp, &mut psbt);
+    finalize_psbt(&secp, cpfp_tx.psbt_mut());
     assert!(
         1000 * (cpfp_fees + spend_tx.fees()).as_sat()
-            / (psbt.global.unsigned_tx.get_weight() as u64 + spend_tx.max_weight())
+            / (cpfp_tx.tx().get_weight() as u64 + spend_tx.max_weight())
             >= spend_tx.max_feerate() * 1000 + added_feerate
     );
+    // This CPFP doesn't bump the Unvault
+    assert!(matches!(
+        unvault_tx.cpfp_package(&cpfp_tx, &cpfp_descriptor, secp),
+        Err(Error::InvalidCpfpChild)
+    ));
 
     roundtrip!(spend_tx, SpendTransaction);
+    assert!(matches!(
+        spend_tx.cpfp_package(&cpfp_tx, &cpfp_descriptor, secp),
+        Err(Error::NotFinalized)
+    ));
     spend_tx.finalize(&secp)?;
     roundtrip!(spend_tx, SpendTransaction);
+    assert_eq!(
+        spend_tx
+            .cpfp_package(&cpfp_tx, &cpfp_descriptor, secp)?
+            .len(),
+        2
+    );
 
     Ok(())
 }
 
+/// Get a derivation index different from `child_number`, to test against the descriptors of
+/// another vault of the same deployment.
+pub fn other_child_number(child_number: bip32::ChildNumber) -> bip32::ChildNumber {
+    bip32::ChildNumber::from(u32::from(child_number) ^ 1)
+}
+
+/// Parse a Revault transaction from a BIP174-serialized PSBT, accepting any transaction
+/// version. The sanity checks are performed as if the version was `TX_VERSION`.
+///
+/// **WARNING**: this is for testing only. A version 1 transaction does not enforce the relative
+/// timelocks (BIP68), so an Unvault output of such a transaction could be spent before the CSV.
+#[cfg(test)]
+pub fn from_raw_psbt_any_version<T: inner_mut::PrivateInnerMut>(
+    raw_psbt: &[u8],
+) -> Result<T, TransactionSerialisationError> {
+    let mut psbt: Psbt = encode::deserialize(raw_psbt)?;
+    let version = psbt.global.unsigned_tx.version;
+    psbt.global.unsigned_tx.version = super::TX_VERSION;
+
+    let mut tx = T::from_psbt_serialized(&encode::serialize(&psbt))?;
+    tx.psbt_mut().global.unsigned_tx.version = version;
+
+    Ok(tx)
+}
+
 pub fn seed_rng(seed: u64) {
     fastrand::seed(seed);
 }
@@ -18,7 +18,7 @@ use miniscript::{
             Global as PsbtGlobal, Input as PsbtIn, Output as PsbtOut,
             PartiallySignedTransaction as Psbt,
         },
-        Amount, Network, OutPoint, Transaction,
+        Address, Amount, Network, OutPoint, Transaction,
     },
     DescriptorTrait,
 };
@@ -86,6 +86,9 @@ impl UnvaultTransaction {
         unvault_descriptor: &DerivedUnvaultDescriptor,
         cpfp_descriptor: &DerivedCpfpDescriptor,
     ) -> Result<UnvaultTransaction, TransactionCreationError> {
+        // Don't create a vault that could never be spent by a standard transaction
+        utils::check_witness_script_size(deposit_input.txout().witness_script())?;
+
         // First, create a dummy transaction to get its weight without Witness
         let dummy_unvault_txout = UnvaultTxOut::new(Amount::from_sat(u64::MAX), unvault_descriptor);
         let dummy_cpfp_txout = CpfpTxOut::new(Amount::from_sat(u64::MAX), cpfp_descriptor);
@@ -128,6 +131,7 @@ impl UnvaultTransaction {
         }
 
         let unvault_txout = UnvaultTxOut::new(Amount::from_sat(unvault_value), unvault_descriptor);
+        utils::check_witness_script_size(unvault_txout.witness_script())?;
         let cpfp_txout = CpfpTxOut::new(Amount::from_sat(UNVAULT_CPFP_VALUE), cpfp_descriptor);
         Ok(UnvaultTransaction(UnvaultTransaction::create_psbt(
             deposit_input,
@@ -136,11 +140,13 @@ impl UnvaultTransaction {
         )))
     }
 
-    fn unvault_txin(
+    /// Get the reference to the Unvault txo. The Unvault output index is not fixed, hence the
+    /// need for the Unvault descriptor. Returns `None` if this transaction doesn't pay to
+    /// `unvault_descriptor`.
+    pub fn unvault_outpoint(
         &self,
         unvault_descriptor: &DerivedUnvaultDescriptor,
-        sequence: u32,
-    ) -> UnvaultTxIn {
+    ) -> Option<OutPoint> {
         let spk = unvault_descriptor.inner().script_pubkey();
         let index = self
             .psbt()
@@ -148,20 +154,37 @@ impl UnvaultTransaction {
             .unsigned_tx
             .output
             .iter()
-            .position(|txo| txo.script_pubkey == spk)
-            .expect("UnvaultTransaction is always created with an Unvault txo");
+            .position(|txo| txo.script_pubkey == spk)?;
+
+        Some(OutPoint {
+            txid: self.psbt().global.unsigned_tx.txid(),
+            vout: index.try_into().expect("There are two outputs"),
+        })
+    }
 
-        // Unwraped above
-        let txo = &self.psbt().global.unsigned_tx.output[index];
+    /// Get the address of the Unvault txo on this `network`, where the funds are locked during
+    /// the Unvault delay. Returns `None` if this transaction doesn't pay to `unvault_descriptor`.
+    pub fn unvault_address(
+        &self,
+        unvault_descriptor: &DerivedUnvaultDescriptor,
+        network: Network,
+    ) -> Option<Address> {
+        let outpoint = self.unvault_outpoint(unvault_descriptor)?;
+        let txo = &self.psbt().global.unsigned_tx.output[outpoint.vout as usize];
+        Some(Address::from_script(&txo.script_pubkey, network).expect("The Unvault txo is a P2WSH"))
+    }
+
+    fn unvault_txin(
+        &self,
+        unvault_descriptor: &DerivedUnvaultDescriptor,
+        sequence: u32,
+    ) -> UnvaultTxIn {
+        let outpoint = self
+            .unvault_outpoint(unvault_descriptor)
+            .expect("UnvaultTransaction is always created with an Unvault txo");
+        let txo = &self.psbt().global.unsigned_tx.output[outpoint.vout as usize];
         let prev_txout = UnvaultTxOut::new(Amount::from_sat(txo.value), unvault_descriptor);
-        UnvaultTxIn::new(
-            OutPoint {
-                txid: self.psbt().global.unsigned_tx.txid(),
-                vout: index.try_into().expect("There are two outputs"),
-            },
-            prev_txout,
-            sequence,
-        )
+        UnvaultTxIn::new(outpoint, prev_txout, sequence)
     }
 
     /// Get the Unvault txo to be referenced in a spending transaction
@@ -204,6 +227,70 @@ impl UnvaultTransaction {
 
         Ok(UnvaultTransaction(psbt))
     }
+
+    /// Get the value of the output paying to this CPFP descriptor, if any. For an Unvault we
+    /// created this is always [UNVAULT_CPFP_VALUE], but a parsed one might differ.
+    pub fn cpfp_value(&self, cpfp_descriptor: &DerivedCpfpDescriptor) -> Option<u64> {
+        let spk = cpfp_descriptor.inner().script_pubkey();
+        self.tx()
+            .output
+            .iter()
+            .find(|txo| txo.script_pubkey == spk)
+            .map(|txo| txo.value)
+    }
+
+    /// Check the outputs of a (parsed) Unvault transaction are the Unvault output we'd have
+    /// created out of a deposit of this value and the [UNVAULT_CPFP_VALUE] CPFP output. Useful
+    /// to make sure a received Unvault doesn't move a different amount before signing it.
+    ///
+    /// Errors with [PsbtValidationError::InsaneAmounts] if the costs of the Unvault overflow or
+    /// are larger than `deposit_value`, and with [PsbtValidationError::MissingInWitnessScript] or
+    /// [PsbtValidationError::InvalidInWitnessScript] if the witness Script can't be recovered
+    /// from the final witness.
+    pub fn validate_unvault_value(&self, deposit_value: u64) -> Result<(), PsbtValidationError> {
+        // Same computation as at creation: a fixed feerate over the maximum satisfied weight.
+        let witstrip_weight: u64 = self.tx().get_weight().try_into().expect("usize in u64");
+        let expected_value = utils::estimated_input_weights(self.psbt())?
+            .into_iter()
+            .try_fold(witstrip_weight, |weight, txin_weight| {
+                weight.checked_add(txin_weight)
+            })
+            .and_then(|total_weight| UNVAULT_TX_FEERATE.checked_mul(total_weight))
+            .and_then(|fees| fees.checked_add(UNVAULT_CPFP_VALUE))
+            // No Unvault output at all could be created out of such a deposit
+            .and_then(|costs| deposit_value.checked_sub(costs))
+            .ok_or(PsbtValidationError::InsaneAmounts)?;
+
+        let mut values: Vec<u64> = self.tx().output.iter().map(|txo| txo.value).collect();
+        values.sort_unstable();
+        let mut expected_values = vec![expected_value, UNVAULT_CPFP_VALUE];
+        expected_values.sort_unstable();
+        if values != expected_values {
+            return Err(PsbtValidationError::InvalidUnvaultValue(expected_value));
+        }
+
+        Ok(())
+    }
+
+    /// Check a (parsed) Unvault transaction has a CPFP output paying [UNVAULT_CPFP_VALUE] to
+    /// this CPFP descriptor. Useful to make sure a received Unvault's CPFP output wasn't tampered
+    /// with before signing it.
+    pub fn check_cpfp_txout(
+        &self,
+        cpfp_descriptor: &DerivedCpfpDescriptor,
+    ) -> Result<(), PsbtValidationError> {
+        let spk = cpfp_descriptor.inner().script_pubkey();
+        if self
+            .tx()
+            .output
+            .iter()
+            .any(|txo| txo.script_pubkey == spk && txo.value == UNVAULT_CPFP_VALUE)
+        {
+            Ok(())
+        } else {
+            Err(PsbtValidationError::InvalidCpfpOutput)
+        }
+    }
 }
 
 impl CpfpableTransaction for UnvaultTransaction {
@@ -245,3 +332,63 @@ impl CpfpableTransaction for UnvaultTransaction {
         weight
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        transactions::{
+            test_utils::{test_vault, TestVault},
+            tests_helpers::other_child_number,
+            RevaultTransaction, UNVAULT_CPFP_VALUE,
+        },
+        txins::RevaultTxIn,
+    };
+
+    use miniscript::bitcoin::Amount;
+
+    #[test]
+    fn unvault_cpfp_value() {
+        let vault = test_vault();
+        let chain = vault
+            .chain(TestVault::default_outpoint(), Amount::from_sat(100_000_000))
+            .unwrap();
+        let unvault_tx = chain.unvault();
+
+        assert_eq!(
+            unvault_tx.cpfp_value(&vault.der_cpfp_descriptor()),
+            Some(UNVAULT_CPFP_VALUE)
+        );
+        let other_cpfp_descriptor = vault
+            .cpfp_descriptor
+            .derive(other_child_number(vault.child_number), &vault.secp);
+        assert_eq!(unvault_tx.cpfp_value(&other_cpfp_descriptor), None);
+    }
+
+    #[test]
+    fn unvault_outpoint() {
+        let vault = test_vault();
+        let chain = vault
+            .chain(TestVault::default_outpoint(), Amount::from_sat(100_000_000))
+            .unwrap();
+        let unvault_tx = chain.unvault();
+        let der_unvault_descriptor = vault.der_unvault_descriptor();
+
+        // It's the outpoint spent by the revocation transactions
+        assert_eq!(
+            unvault_tx.unvault_outpoint(&der_unvault_descriptor),
+            Some(
+                unvault_tx
+                    .revault_unvault_txin(&der_unvault_descriptor)
+                    .outpoint()
+            )
+        );
+        assert_eq!(
+            unvault_tx.unvault_outpoint(&der_unvault_descriptor),
+            Some(chain.cancel_batch().feerate_20().tx().input[0].previous_output)
+        );
+        let other_unvault_descriptor = vault
+            .unvault_descriptor
+            .derive(other_child_number(vault.child_number), &vault.secp);
+        assert_eq!(unvault_tx.unvault_outpoint(&other_unvault_descriptor), None);
+    }
+}
@@ -2,8 +2,8 @@ use crate::{
     error::*,
     scripts::*,
     transactions::{
-        utils, RevaultPresignedTransaction, RevaultTransaction, EMER_TX_FEERATE, INSANE_FEES,
-        MAX_STANDARD_TX_WEIGHT,
+        utils, RevaultPresignedTransaction, RevaultTransaction, EMER_OUTPUT_MIN_SATS,
+        EMER_TX_FEERATE, INSANE_FEES, MAX_STANDARD_TX_WEIGHT,
     },
     txins::*,
     txouts::*,
@@ -29,13 +29,47 @@ impl_revault_transaction!(
 impl RevaultPresignedTransaction for UnvaultEmergencyTransaction {}
 impl UnvaultEmergencyTransaction {
     /// The second emergency transaction always spends an unvault output and pays to the Emergency
-    /// Script.
+    /// Script. It is created with a fixed feerate of [EMER_TX_FEERATE].
     ///
     /// BIP174 Creator and Updater roles.
     pub fn new(
         unvault_input: UnvaultTxIn,
         emer_address: EmergencyAddress,
     ) -> Result<UnvaultEmergencyTransaction, TransactionCreationError> {
+        UnvaultEmergencyTransaction::create(
+            unvault_input,
+            emer_address,
+            Amount::from_sat(EMER_TX_FEERATE),
+        )
+    }
+
+    /// Same as [UnvaultEmergencyTransaction::new] but with a custom `feerate`, in sats/WU.
+    /// Will error with [TransactionCreationError::Dust] if the Unvault output cannot afford it
+    /// while leaving an Emergency output of at least [EMER_OUTPUT_MIN_SATS], with
+    /// [TransactionCreationError::FeerateTooHigh] if the fees overflow and with
+    /// [TransactionCreationError::InsaneFees] if they are larger than [INSANE_FEES].
+    ///
+    /// BIP174 Creator and Updater roles.
+    pub fn with_feerate(
+        unvault_input: UnvaultTxIn,
+        emer_address: EmergencyAddress,
+        feerate: Amount,
+    ) -> Result<UnvaultEmergencyTransaction, TransactionCreationError> {
+        let unemer_tx = UnvaultEmergencyTransaction::create(unvault_input, emer_address, feerate)?;
+        if unemer_tx.tx().output[0].value < EMER_OUTPUT_MIN_SATS {
+            return Err(TransactionCreationError::Dust);
+        }
+
+        Ok(unemer_tx)
+    }
+
+    fn create(
+        unvault_input: UnvaultTxIn,
+        emer_address: EmergencyAddress,
+        feerate: Amount,
+    ) -> Result<UnvaultEmergencyTransaction, TransactionCreationError> {
+        utils::check_witness_script_size(unvault_input.txout().witness_script())?;
+
         // First, create a dummy transaction to get its weight without Witness.
         let emer_txo = EmergencyTxOut::new(emer_address.clone(), Amount::from_sat(u64::MAX));
         let dummy_tx = utils::create_psbt(unvault_input.clone(), emer_txo)
@@ -49,10 +83,10 @@ impl UnvaultEmergencyTransaction {
             .checked_add(unvault_input.txout().max_sat_weight())
             .expect("Weight computation bug");
         let total_weight: u64 = total_weight.try_into().expect("usize in u64");
-        let fees = EMER_TX_FEERATE
+        let fees = feerate
+            .as_sat()
             .checked_mul(total_weight)
-            .expect("Weight computation bug");
-        assert!(fees < INSANE_FEES);
+            .ok_or(TransactionCreationError::FeerateTooHigh)?;
 
         assert!(
             total_weight <= MAX_STANDARD_TX_WEIGHT as u64,
@@ -63,7 +97,11 @@ impl UnvaultEmergencyTransaction {
         let deposit_value = unvault_input.txout().txout().value;
         let emer_value = deposit_value
             .checked_sub(fees)
-            .expect("We would never create a dust unvault txo");
+            .ok_or(TransactionCreationError::Dust)?;
+        // Nobody wants to pay 3k€ fees if we had a bug.
+        if fees > INSANE_FEES {
+            return Err(TransactionCreationError::InsaneFees);
+        }
         assert!(
             emer_value < max_money(Network::Bitcoin),
             "Checked in UnvaultTransaction constructor already"
@@ -103,4 +141,150 @@ impl UnvaultEmergencyTransaction {
             vout: 0,
         }
     }
+
+    /// Preview the output values of this transaction if its fees were raised to
+    /// `target_feerate` (in sats/WU). This does not modify the transaction.
+    pub fn simulate_feebump(
+        &self,
+        target_feerate: u64,
+    ) -> Result<Vec<u64>, TransactionCreationError> {
+        utils::revocation_feebump_values(self.psbt(), target_feerate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnvaultEmergencyTransaction;
+    use crate::{
+        error::*,
+        transactions::{
+            test_utils::{test_vault, TestVault},
+            RevaultTransaction, UnvaultTransaction, EMER_OUTPUT_MIN_SATS, EMER_TX_FEERATE,
+            INSANE_FEES,
+        },
+        txins::*,
+        txouts::*,
+    };
+
+    use miniscript::bitcoin::{blockdata::constants::COIN_VALUE, Amount};
+
+    fn unvault_txin(vault: &TestVault, deposit_value: Amount) -> UnvaultTxIn {
+        UnvaultTransaction::new(
+            vault.deposit_txin(TestVault::default_outpoint(), deposit_value),
+            &vault.der_unvault_descriptor(),
+            &vault.der_cpfp_descriptor(),
+        )
+        .unwrap()
+        .revault_unvault_txin(&vault.der_unvault_descriptor())
+    }
+
+    // The same Unvault txin, but spending an output of `value`
+    fn with_value(vault: &TestVault, unvault_txin: &UnvaultTxIn, value: u64) -> UnvaultTxIn {
+        UnvaultTxIn::new(
+            unvault_txin.outpoint(),
+            UnvaultTxOut::new(Amount::from_sat(value), &vault.der_unvault_descriptor()),
+            unvault_txin.sequence(),
+        )
+    }
+
+    #[test]
+    fn unvault_emergency_with_feerate() {
+        let vault = test_vault();
+        // Large enough for insane fees to be reached before exhausting the Unvault output
+        let unvault_txin = unvault_txin(&vault, Amount::from_sat(COIN_VALUE));
+        let unvault_value = unvault_txin.txout().txout().value;
+        let unemergency_tx =
+            UnvaultEmergencyTransaction::new(unvault_txin.clone(), vault.emergency_address.clone())
+                .unwrap();
+        let unemer_weight = unemergency_tx.fees().as_sat() / EMER_TX_FEERATE;
+
+        assert_eq!(
+            UnvaultEmergencyTransaction::with_feerate(
+                unvault_txin.clone(),
+                vault.emergency_address.clone(),
+                Amount::from_sat(EMER_TX_FEERATE)
+            ),
+            Ok(unemergency_tx)
+        );
+        assert_eq!(
+            UnvaultEmergencyTransaction::with_feerate(
+                unvault_txin.clone(),
+                vault.emergency_address.clone(),
+                Amount::from_sat(u64::MAX)
+            ),
+            Err(TransactionCreationError::FeerateTooHigh)
+        );
+        assert_eq!(
+            UnvaultEmergencyTransaction::with_feerate(
+                unvault_txin.clone(),
+                vault.emergency_address.clone(),
+                Amount::from_sat(INSANE_FEES / unemer_weight + 1)
+            ),
+            Err(TransactionCreationError::InsaneFees)
+        );
+        // Fees larger than the Unvault output
+        assert_eq!(
+            UnvaultEmergencyTransaction::with_feerate(
+                unvault_txin.clone(),
+                vault.emergency_address.clone(),
+                Amount::from_sat(unvault_value)
+            ),
+            Err(TransactionCreationError::Dust)
+        );
+
+        // Just enough fees to leave less than the minimum Emergency output value
+        let unvault_txin = with_value(
+            &vault,
+            &unvault_txin,
+            unemer_weight * 1_000 + EMER_OUTPUT_MIN_SATS,
+        );
+        UnvaultEmergencyTransaction::with_feerate(
+            unvault_txin.clone(),
+            vault.emergency_address.clone(),
+            Amount::from_sat(1_000),
+        )
+        .expect("Leaves exactly the minimum Emergency output value");
+        assert_eq!(
+            UnvaultEmergencyTransaction::with_feerate(
+                unvault_txin,
+                vault.emergency_address.clone(),
+                Amount::from_sat(1_001)
+            ),
+            Err(TransactionCreationError::Dust)
+        );
+    }
+
+    #[test]
+    fn unvault_emergency_min_output() {
+        let vault = test_vault();
+        let unvault_txin = unvault_txin(&vault, Amount::from_sat(COIN_VALUE));
+        let unemer_fees =
+            UnvaultEmergencyTransaction::new(unvault_txin.clone(), vault.emergency_address.clone())
+                .unwrap()
+                .fees()
+                .as_sat();
+
+        // The minimum Emergency output value is only enforced for a custom feerate, the default
+        // constructor accepts any Unvault output that can pay for its fees.
+        let unvault_txin = with_value(
+            &vault,
+            &unvault_txin,
+            unemer_fees + EMER_OUTPUT_MIN_SATS - 1,
+        );
+        let unemergency_tx =
+            UnvaultEmergencyTransaction::new(unvault_txin.clone(), vault.emergency_address.clone())
+                .unwrap();
+        assert_eq!(
+            unemergency_tx.tx().output[0].value,
+            EMER_OUTPUT_MIN_SATS - 1
+        );
+        assert_eq!(
+            UnvaultEmergencyTransaction::with_feerate(
+                unvault_txin,
+                vault.emergency_address.clone(),
+                Amount::from_sat(EMER_TX_FEERATE)
+            ),
+            Err(TransactionCreationError::Dust)
+        );
+    }
 }
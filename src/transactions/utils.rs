@@ -1,17 +1,28 @@
 use crate::{
     error::*,
-    transactions::{TX_LOCKTIME, TX_VERSION},
+    scripts::DerivedPublicKey,
+    transactions::{MAX_STANDARD_P2WSH_SCRIPT_SIZE, TX_LOCKTIME, TX_VERSION},
     txins::RevaultTxIn,
-    txouts::{RevaultInternalTxOut, RevaultTxOut},
+    txouts::{RevaultInternalTxOut, RevaultTxOut, MAX_OP_RETURN_DATA},
 };
 
-use miniscript::bitcoin::{
-    blockdata::constants::max_money,
-    util::psbt::{Global as PsbtGlobal, Input as PsbtIn, PartiallySignedTransaction as Psbt},
-    Amount, Network, OutPoint, Transaction,
+use miniscript::{
+    bitcoin::{
+        blockdata::{constants::max_money, opcodes, script::Instruction},
+        consensus::encode::{serialize, Encodable, VarInt},
+        util::bip32::{DerivationPath, Fingerprint, KeySource},
+        util::psbt::{
+            raw, Global as PsbtGlobal, Input as PsbtIn, Map, PartiallySignedTransaction as Psbt,
+        },
+        Amount, Network, OutPoint, PublicKey, Script, SigHashType, Transaction, TxOut,
+    },
+    Descriptor, DescriptorTrait, ForEachKey,
 };
 
-use std::collections::{BTreeMap, HashSet};
+use std::{
+    collections::{BTreeMap, HashSet},
+    convert::TryInto,
+};
 
 /// Boilerplate for defining a Revault transaction as a newtype over a Psbt and implementing
 /// RevaultTransaction for it.
@@ -142,6 +153,13 @@ pub fn psbt_common_sanity_checks(psbt: Psbt) -> Result<Psbt, PsbtValidationError
             return Err(PsbtValidationError::InvalidInputField(input.clone()));
         }
 
+        // We only ever compute the sighashes for, sign and verify SIGHASH_ALL signatures. A
+        // PSBT input requesting another type would have signers commit to something else than
+        // what we check.
+        if input.sighash_type.map_or(false, |t| t != SigHashType::All) {
+            return Err(PsbtValidationError::InvalidSigHashType(input.clone()));
+        }
+
         // Make sure it does not mix finalized and non-finalized inputs or final scripts
         // and non-final scripts.
         if input.final_script_witness.is_some() {
@@ -242,6 +260,470 @@ pub fn psbt_fees(psbt: &Psbt) -> Option<Amount> {
     value_in.checked_sub(value_out)
 }
 
+/// Whether this Script is of a type standard transaction outputs may pay to: P2PKH, P2SH,
+/// P2WPKH, P2WSH, P2TR or null data (OP_RETURN).
+pub fn is_standard_script_pubkey(script: &Script) -> bool {
+    let bytes = script.as_bytes();
+    // OP_RETURN followed only by pushes, of up to MAX_OP_RETURN_DATA bytes of data along with
+    // their opcodes (a PUSHDATA1 of the maximum size).
+    let is_null_data = script.is_op_return()
+        && bytes.len() <= MAX_OP_RETURN_DATA + 3
+        && Script::from(bytes[1..].to_vec())
+            .instructions()
+            .all(|instr| match instr {
+                Ok(Instruction::PushBytes(_)) => true,
+                Ok(Instruction::Op(op)) => op.into_u8() <= opcodes::all::OP_PUSHNUM_16.into_u8(),
+                Err(_) => false,
+            });
+    // Segwit v1 with a 32 bytes program
+    let is_p2tr =
+        bytes.len() == 34 && bytes[0] == opcodes::all::OP_PUSHNUM_1.into_u8() && bytes[1] == 0x20;
+
+    is_null_data
+        || script.is_p2pkh()
+        || script.is_p2sh()
+        || script.is_v0_p2wpkh()
+        || script.is_v0_p2wsh()
+        || is_p2tr
+}
+
+/// Get the witness version and the witness program of this Script, if it's a witness program.
+pub fn witness_program(script: &Script) -> Option<(u8, Vec<u8>)> {
+    if !script.is_witness_program() {
+        return None;
+    }
+
+    let bytes = script.as_bytes();
+    // OP_0 or OP_PUSHNUM_1 to OP_PUSHNUM_16, then the push of the program
+    let version = match bytes[0] {
+        0 => 0,
+        v => v - opcodes::all::OP_PUSHNUM_1.into_u8() + 1,
+    };
+
+    Some((version, bytes[2..].to_vec()))
+}
+
+/// Make sure a witness Script could be spent by a standard transaction.
+pub fn check_witness_script_size(script: &Script) -> Result<(), TransactionCreationError> {
+    let size = script.len();
+    if size > MAX_STANDARD_P2WSH_SCRIPT_SIZE {
+        return Err(TransactionCreationError::ScriptTooLarge(size));
+    }
+
+    Ok(())
+}
+
+/// Sum these `weights`, erroring with [TransactionCreationError::WeightOverflow] instead of
+/// overflowing.
+pub fn sum_weights<I: IntoIterator<Item = usize>>(
+    weights: I,
+) -> Result<usize, TransactionCreationError> {
+    weights
+        .into_iter()
+        .try_fold(0usize, |sum, weight| sum.checked_add(weight))
+        .ok_or(TransactionCreationError::WeightOverflow)
+}
+
+/// Preview the output values of a single-output revocation transaction if its fees were
+/// raised to `target_feerate` (in sats/WU), assuming fully-satisfied inputs. The increase is
+/// entirely deducted from the (single) output. Outputs are left untouched if the transaction
+/// already pays at least this feerate.
+pub fn revocation_feebump_values(
+    psbt: &Psbt,
+    target_feerate: u64,
+) -> Result<Vec<u64>, TransactionCreationError> {
+    let outputs = &psbt.global.unsigned_tx.output;
+    assert_eq!(
+        outputs.len(),
+        1,
+        "Revocation transactions have a single output"
+    );
+
+    let current_fees = psbt_fees(psbt)
+        .expect("Checked at creation and parsing time")
+        .as_sat();
+    let target_fees = target_feerate
+        .checked_mul(max_weight(psbt))
+        .ok_or(TransactionCreationError::FeerateTooHigh)?;
+    let added_fees = target_fees.saturating_sub(current_fees);
+
+    let txo = &outputs[0];
+    let value = txo
+        .value
+        .checked_sub(added_fees)
+        .ok_or(TransactionCreationError::FeerateTooHigh)?;
+    if value < txo.script_pubkey.dust_value().as_sat() {
+        return Err(TransactionCreationError::FeerateTooHigh);
+    }
+
+    Ok(vec![value])
+}
+
+/// Get the length of the BIP174 serialization of this PSBT, computed from its fields.
+pub fn psbt_serialized_len(psbt: &Psbt) -> usize {
+    fn varint_len(n: usize) -> usize {
+        VarInt(n as u64).len()
+    }
+    // A key-value pair: <keylen><keytype><keydata> <valuelen><valuedata>
+    fn pair_len(key_data_len: usize, value_len: usize) -> usize {
+        varint_len(key_data_len + 1) + 1 + key_data_len + varint_len(value_len) + value_len
+    }
+    fn proprietary_len(proprietary: &BTreeMap<raw::ProprietaryKey, Vec<u8>>) -> usize {
+        proprietary
+            .iter()
+            .map(|(key, value)| {
+                let key_data_len =
+                    varint_len(key.prefix.len()) + key.prefix.len() + 1 + key.key.len();
+                pair_len(key_data_len, value.len())
+            })
+            .sum()
+    }
+    fn unknown_len(unknown: &BTreeMap<raw::Key, Vec<u8>>) -> usize {
+        unknown
+            .iter()
+            .map(|(key, value)| pair_len(key.key.len(), value.len()))
+            .sum()
+    }
+    fn pubkey_len(pubkey: &PublicKey) -> usize {
+        if pubkey.compressed {
+            33
+        } else {
+            65
+        }
+    }
+    fn bip32_len(derivations: &BTreeMap<PublicKey, KeySource>) -> usize {
+        derivations
+            .iter()
+            .map(|(pubkey, (_, path))| pair_len(pubkey_len(pubkey), 4 + 4 * path.as_ref().len()))
+            .sum()
+    }
+    fn script_len(script: &Option<Script>) -> usize {
+        script.as_ref().map(|s| pair_len(0, s.len())).unwrap_or(0)
+    }
+    fn txout_len(txout: &TxOut) -> usize {
+        8 + varint_len(txout.script_pubkey.len()) + txout.script_pubkey.len()
+    }
+    fn preimages_len<H>(preimages: &BTreeMap<H, Vec<u8>>, hash_len: usize) -> usize {
+        preimages
+            .values()
+            .map(|preimage| pair_len(hash_len, preimage.len()))
+            .sum()
+    }
+
+    // The magic bytes and the separator
+    let mut len = 5;
+
+    let global = &psbt.global;
+    let tx = &global.unsigned_tx;
+    // The unsigned transaction is always serialized without witness
+    let tx_len = 4
+        + varint_len(tx.input.len())
+        + tx.input
+            .iter()
+            .map(|txin| 40 + varint_len(txin.script_sig.len()) + txin.script_sig.len())
+            .sum::<usize>()
+        + varint_len(tx.output.len())
+        + tx.output.iter().map(txout_len).sum::<usize>()
+        + 4;
+    len += pair_len(0, tx_len);
+    len += global
+        .xpub
+        .values()
+        .map(|(_, path)| pair_len(78, 4 + 4 * path.as_ref().len()))
+        .sum::<usize>();
+    if global.version > 0 {
+        len += pair_len(0, 4);
+    }
+    len += proprietary_len(&global.proprietary) + unknown_len(&global.unknown) + 1;
+
+    for input in psbt.inputs.iter() {
+        len += input
+            .non_witness_utxo
+            .as_ref()
+            .map(|tx| pair_len(0, tx.get_size()))
+            .unwrap_or(0);
+        len += input
+            .witness_utxo
+            .as_ref()
+            .map(|txo| pair_len(0, txout_len(txo)))
+            .unwrap_or(0);
+        len += input
+            .partial_sigs
+            .iter()
+            .map(|(pubkey, sig)| pair_len(pubkey_len(pubkey), sig.len()))
+            .sum::<usize>();
+        if input.sighash_type.is_some() {
+            len += pair_len(0, 4);
+        }
+        len += script_len(&input.redeem_script) + script_len(&input.witness_script);
+        len += bip32_len(&input.bip32_derivation);
+        len += script_len(&input.final_script_sig);
+        len += input
+            .final_script_witness
+            .as_ref()
+            .map(|witness| {
+                let witness_len = varint_len(witness.len())
+                    + witness
+                        .iter()
+                        .map(|item| varint_len(item.len()) + item.len())
+                        .sum::<usize>();
+                pair_len(0, witness_len)
+            })
+            .unwrap_or(0);
+        len += preimages_len(&input.ripemd160_preimages, 20)
+            + preimages_len(&input.sha256_preimages, 32)
+            + preimages_len(&input.hash160_preimages, 20)
+            + preimages_len(&input.hash256_preimages, 32);
+        len += proprietary_len(&input.proprietary) + unknown_len(&input.unknown) + 1;
+    }
+
+    for output in psbt.outputs.iter() {
+        len += script_len(&output.redeem_script) + script_len(&output.witness_script);
+        len += bip32_len(&output.bip32_derivation);
+        len += proprietary_len(&output.proprietary) + unknown_len(&output.unknown) + 1;
+    }
+
+    len
+}
+
+/// Serialize a PSBT in the version 2 format of BIP370: the global unsigned transaction is
+/// replaced by per-input and per-output fields.
+pub fn psbt_v2_serialized(psbt: &Psbt) -> Vec<u8> {
+    // BIP174 and BIP370 global, input and output types
+    const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+    const PSBT_GLOBAL_TX_VERSION: u8 = 0x02;
+    const PSBT_GLOBAL_FALLBACK_LOCKTIME: u8 = 0x03;
+    const PSBT_GLOBAL_INPUT_COUNT: u8 = 0x04;
+    const PSBT_GLOBAL_OUTPUT_COUNT: u8 = 0x05;
+    const PSBT_GLOBAL_VERSION: u8 = 0xFB;
+    const PSBT_IN_PREVIOUS_TXID: u8 = 0x0e;
+    const PSBT_IN_OUTPUT_INDEX: u8 = 0x0f;
+    const PSBT_IN_SEQUENCE: u8 = 0x10;
+    const PSBT_OUT_AMOUNT: u8 = 0x03;
+    const PSBT_OUT_SCRIPT: u8 = 0x04;
+
+    fn pair(type_value: u8, value: Vec<u8>) -> raw::Pair {
+        raw::Pair {
+            key: raw::Key {
+                type_value,
+                key: vec![],
+            },
+            value,
+        }
+    }
+
+    fn write_map(buf: &mut Vec<u8>, pairs: Vec<raw::Pair>) {
+        for pair in pairs {
+            pair.consensus_encode(&mut *buf)
+                .expect("Writing to a Vec can't fail");
+        }
+        buf.push(0x00);
+    }
+
+    let tx = &psbt.global.unsigned_tx;
+    let mut buf = Vec::with_capacity(256);
+    buf.extend_from_slice(b"psbt\xff");
+
+    let mut global_pairs: Vec<raw::Pair> = psbt
+        .global
+        .get_pairs()
+        .expect("Writing to a Vec can't fail")
+        .into_iter()
+        .filter(|pair| {
+            pair.key.type_value != PSBT_GLOBAL_UNSIGNED_TX
+                && pair.key.type_value != PSBT_GLOBAL_VERSION
+        })
+        .collect();
+    global_pairs.push(pair(PSBT_GLOBAL_TX_VERSION, serialize(&tx.version)));
+    global_pairs.push(pair(
+        PSBT_GLOBAL_FALLBACK_LOCKTIME,
+        serialize(&tx.lock_time),
+    ));
+    global_pairs.push(pair(
+        PSBT_GLOBAL_INPUT_COUNT,
+        serialize(&VarInt(tx.input.len() as u64)),
+    ));
+    global_pairs.push(pair(
+        PSBT_GLOBAL_OUTPUT_COUNT,
+        serialize(&VarInt(tx.output.len() as u64)),
+    ));
+    global_pairs.push(pair(PSBT_GLOBAL_VERSION, serialize(&2u32)));
+    write_map(&mut buf, global_pairs);
+
+    for (txin, psbtin) in tx.input.iter().zip(psbt.inputs.iter()) {
+        let mut pairs = psbtin.get_pairs().expect("Writing to a Vec can't fail");
+        pairs.push(pair(
+            PSBT_IN_PREVIOUS_TXID,
+            serialize(&txin.previous_output.txid),
+        ));
+        pairs.push(pair(
+            PSBT_IN_OUTPUT_INDEX,
+            serialize(&txin.previous_output.vout),
+        ));
+        pairs.push(pair(PSBT_IN_SEQUENCE, serialize(&txin.sequence)));
+        write_map(&mut buf, pairs);
+    }
+
+    for (txout, psbtout) in tx.output.iter().zip(psbt.outputs.iter()) {
+        let mut pairs = psbtout.get_pairs().expect("Writing to a Vec can't fail");
+        pairs.push(pair(PSBT_OUT_AMOUNT, serialize(&txout.value)));
+        pairs.push(pair(PSBT_OUT_SCRIPT, txout.script_pubkey.to_bytes()));
+        write_map(&mut buf, pairs);
+    }
+
+    buf
+}
+
+/// Returns the weight of the witness of each input of a PSBT. If an input is already finalized,
+/// returns the exact weight of its serialized witness. Otherwise computes the maximum reasonable weight of
+/// a satisfaction for this P2WSH input.
+pub fn input_weights(psbt: &Psbt) -> Vec<u64> {
+    psbt.inputs
+        .iter()
+        .map(|txin| {
+            if let Some(ref witness) = txin.final_script_witness {
+                // The serialized witness: the number of stack items and each length-prefixed item
+                witness
+                    .iter()
+                    .map(|e| VarInt(e.len() as u64).len() + e.len())
+                    .sum::<usize>()
+                    .checked_add(VarInt(witness.len() as u64).len())
+                    .expect("Bug: witness size >usize::MAX")
+                    .try_into()
+                    .expect("Bug: witness size >u64::MAX")
+            } else {
+                // FIXME: this panic can probably be triggered...
+                miniscript::descriptor::Wsh::new(
+                    miniscript::Miniscript::parse(
+                        txin.witness_script
+                            .as_ref()
+                            .expect("Revault txins always have a witness Script"),
+                    )
+                    .expect("Revault txins witness_script is created from a Miniscript"),
+                )
+                .expect("")
+                .max_satisfaction_weight()
+                .expect("It's a sane Script, derived from a Miniscript")
+                .try_into()
+                .expect("Can't be >u64::MAX")
+            }
+        })
+        .collect()
+}
+
+/// Get the maximum satisfaction weight of each input, as used for the fee computation at creation
+/// time. Unlike [input_weights], this does not use the witness of finalized inputs other than for
+/// recovering their witness Script.
+///
+/// Errors if the witness Script of an input is missing or isn't a P2WSH Miniscript, as can be the
+/// case for the final witness of a parsed transaction.
+pub fn estimated_input_weights(psbt: &Psbt) -> Result<Vec<u64>, PsbtValidationError> {
+    psbt.inputs
+        .iter()
+        .map(|txin| {
+            let witness_script = match txin.final_script_witness {
+                // The witness Script is always the last element of a P2WSH witness
+                Some(ref witness) => witness.last().map(|script| Script::from(script.clone())),
+                None => txin.witness_script.clone(),
+            }
+            .ok_or_else(|| PsbtValidationError::MissingInWitnessScript(txin.clone()))?;
+
+            let weight = miniscript::Miniscript::parse(&witness_script)
+                .ok()
+                .and_then(|ms| miniscript::descriptor::Wsh::new(ms).ok())
+                .and_then(|wsh| wsh.max_satisfaction_weight().ok())
+                .ok_or_else(|| PsbtValidationError::InvalidInWitnessScript(txin.clone()))?;
+
+            Ok(weight.try_into().expect("Can't be >u64::MAX"))
+        })
+        .collect()
+}
+
+/// Returns the weight of a PSBT, assuming fully-satisfied inputs. If it is already finalized,
+/// returns the exact weight. Otherwise computes the maximum reasonable weight of a satisfaction
+/// for each P2WSH input.
+pub fn max_weight(psbt: &Psbt) -> u64 {
+    let tx = &psbt.global.unsigned_tx;
+
+    let mut weight: u64 = tx.get_weight().try_into().expect("Can't be >u64::MAX");
+    for txin_weight in input_weights(psbt) {
+        weight = weight
+            .checked_add(txin_weight)
+            .expect("Weight computation bug: overflow computing spent coins value");
+    }
+    assert!(weight > 0, "We never create an empty tx");
+
+    weight
+}
+
+/// Get a copy of this PSBT with the fields a watch-only wallet needs to track it filled in: the
+/// sighash type of each input, the witness Script and BIP32 derivations of each output we have
+/// a descriptor for, and the origin of the keys of all the BIP32 derivations.
+///
+/// The derivations we create record the fingerprint of the xpub of the descriptor and the
+/// derivation index. `key_origins` maps the fingerprint of each xpub to its origin, that is the
+/// fingerprint of its master key and its derivation path from it. Errors if the origin of a key
+/// is unknown.
+pub fn watchonly_psbt(
+    psbt: &Psbt,
+    descriptors: &[&Descriptor<DerivedPublicKey>],
+    key_origins: &BTreeMap<Fingerprint, KeySource>,
+) -> Result<Psbt, PsbtValidationError> {
+    let mut psbt = psbt.clone();
+
+    for psbtin in psbt.inputs.iter_mut() {
+        if psbtin.final_script_witness.is_none() && psbtin.sighash_type.is_none() {
+            psbtin.sighash_type = Some(SigHashType::All);
+        }
+        if !with_key_origins(&mut psbtin.bip32_derivation, key_origins) {
+            return Err(PsbtValidationError::InvalidInputField(psbtin.clone()));
+        }
+    }
+
+    let txos = &psbt.global.unsigned_tx.output;
+    for (txo, psbtout) in txos.iter().zip(psbt.outputs.iter_mut()) {
+        if let Some(desc) = descriptors
+            .iter()
+            .find(|desc| desc.script_pubkey() == txo.script_pubkey)
+        {
+            psbtout.witness_script = Some(desc.explicit_script());
+            if psbtout.bip32_derivation.is_empty() {
+                desc.for_each_key(|key| {
+                    let key = key.as_key();
+                    psbtout.bip32_derivation.insert(
+                        key.key,
+                        (key.origin.0, DerivationPath::from(&[key.origin.1][..])),
+                    );
+                    true
+                });
+            }
+        }
+        if !with_key_origins(&mut psbtout.bip32_derivation, key_origins) {
+            return Err(PsbtValidationError::InvalidOutputField(psbtout.clone()));
+        }
+    }
+
+    Ok(psbt)
+}
+
+// Prepend the path from the master key to the xpub to the derivation path of each key. Returns
+// false if the origin of a key is unknown.
+fn with_key_origins(
+    bip32_derivation: &mut BTreeMap<PublicKey, KeySource>,
+    key_origins: &BTreeMap<Fingerprint, KeySource>,
+) -> bool {
+    for (fingerprint, der_path) in bip32_derivation.values_mut() {
+        let (master_fingerprint, xpub_path) = match key_origins.get(fingerprint) {
+            Some(origin) => origin,
+            None => return false,
+        };
+        *der_path = xpub_path.extend(der_path.as_ref());
+        *fingerprint = *master_fingerprint;
+    }
+
+    true
+}
+
 /// Create a single-input single-output PSBT.
 /// PSBT information is filled depending on the input/output type.
 pub fn create_psbt<Out: RevaultTxOut, IntOut: RevaultInternalTxOut, In: RevaultTxIn<IntOut>>(
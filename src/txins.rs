@@ -3,15 +3,36 @@
 //! Wrappers around bitcoin's OutPoint and previous TxOut to statically check Revault
 //! transaction creation and ease PSBT management.
 
-use crate::txouts::{CpfpTxOut, DepositTxOut, UnvaultTxOut};
+use crate::{
+    error::TxoutCreationError,
+    scripts::{DerivedDepositDescriptor, SEQUENCE_LOCKTIME_MASK},
+    txouts::{CpfpTxOut, DepositTxOut, UnvaultTxOut},
+};
 
-use miniscript::bitcoin::{OutPoint, TxIn};
+use miniscript::{
+    bitcoin::{Amount, OutPoint, TxIn, TxOut},
+    miniscript::limits::{SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_TYPE_FLAG},
+    DescriptorTrait,
+};
 
 use std::fmt;
 
 /// The default sequence used by bitcoind to signal for RBF: 0xff_ff_ff_fd
 pub const RBF_SEQUENCE: u32 = u32::MAX - 2;
 
+/// Get the relative timelock, in blocks, this nSequence encodes. This is `Some(0)` if it doesn't
+/// enable one, and `None` if it enables a time-based relative timelock.
+pub fn sequence_csv(sequence: u32) -> Option<u32> {
+    if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+        return Some(0);
+    }
+    if sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+        return None;
+    }
+
+    Some(sequence & SEQUENCE_LOCKTIME_MASK)
+}
+
 /// A transaction input used by a Revault transaction.
 pub trait RevaultTxIn<T>: fmt::Debug + Clone + PartialEq {
     /// Get the actual outpoint
@@ -55,6 +76,20 @@ macro_rules! implem_revault_txin {
                 }
             }
         }
+
+        impl $struct_name {
+            /// Get the raw nSequence of this txin.
+            pub fn sequence(&self) -> u32 {
+                self.sequence
+            }
+
+            /// Get the relative timelock, in blocks, this txin's nSequence encodes. This is
+            /// `Some(0)` if it doesn't enable one, as for the txins signaling for RBF, and `None`
+            /// if it enables a time-based one.
+            pub fn csv(&self) -> Option<u32> {
+                sequence_csv(self.sequence)
+            }
+        }
     };
 }
 
@@ -73,6 +108,32 @@ impl DepositTxIn {
             sequence: RBF_SEQUENCE,
         }
     }
+
+    /// Instanciate a TxIn referencing a deposit txout with a relative timelock of `csv` blocks,
+    /// for deployments that encumber the deposit itself with a CSV. The nSequence is set to `csv`
+    /// as is, and the Unvault and Emergency transactions spending it will carry it.
+    pub fn new_with_csv(outpoint: OutPoint, prev_txout: DepositTxOut, csv: u32) -> DepositTxIn {
+        DepositTxIn {
+            outpoint,
+            prev_txout,
+            sequence: csv,
+        }
+    }
+
+    /// Instanciate a TxIn referencing a deposit txout which signals for RBF, checking the
+    /// given `txout` (e.g. as fetched from the chain) actually pays to this deposit descriptor.
+    pub fn new_checked(
+        outpoint: OutPoint,
+        txout: &TxOut,
+        deposit_descriptor: &DerivedDepositDescriptor,
+    ) -> Result<DepositTxIn, TxoutCreationError> {
+        if txout.script_pubkey != deposit_descriptor.inner().script_pubkey() {
+            return Err(TxoutCreationError::InvalidScriptPubkeyType);
+        }
+        let prev_txout = DepositTxOut::new(Amount::from_sat(txout.value), deposit_descriptor);
+
+        Ok(DepositTxIn::new(outpoint, prev_txout))
+    }
 }
 
 implem_revault_txin!(
@@ -111,3 +172,40 @@ impl CpfpTxIn {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::DepositTxIn;
+    use crate::{
+        error::TxoutCreationError,
+        transactions::{
+            test_utils::{test_vault, TestVault},
+            tests_helpers::other_child_number,
+        },
+        txouts::{DepositTxOut, RevaultTxOut},
+    };
+
+    use miniscript::bitcoin::Amount;
+
+    #[test]
+    fn deposit_txin_checked() {
+        let vault = test_vault();
+        let outpoint = TestVault::default_outpoint();
+        let der_deposit_descriptor = vault.der_deposit_descriptor();
+        let txout =
+            DepositTxOut::new(Amount::from_sat(1_000_000), &der_deposit_descriptor).into_txout();
+
+        assert_eq!(
+            DepositTxIn::new_checked(outpoint, &txout, &der_deposit_descriptor),
+            Ok(vault.deposit_txin(outpoint, Amount::from_sat(1_000_000)))
+        );
+        // The txout must pay to the deposit descriptor at this very index
+        let other_deposit_descriptor = vault
+            .deposit_descriptor
+            .derive(other_child_number(vault.child_number), &vault.secp);
+        assert_eq!(
+            DepositTxIn::new_checked(outpoint, &txout, &other_deposit_descriptor),
+            Err(TxoutCreationError::InvalidScriptPubkeyType)
+        );
+    }
+}
@@ -3,12 +3,16 @@
 //! Wrappers around bitcoin's TxOut to statically check Revault transactions creation and ease
 //! their PSBT management.
 
-use crate::scripts::{
-    DerivedCpfpDescriptor, DerivedDepositDescriptor, DerivedUnvaultDescriptor, EmergencyAddress,
+use crate::{
+    error::TxoutCreationError,
+    scripts::{
+        DerivedCpfpDescriptor, DerivedDepositDescriptor, DerivedUnvaultDescriptor, EmergencyAddress,
+    },
 };
 
 use miniscript::{
     bitcoin::{
+        blockdata::{opcodes, script},
         util::{bip32, psbt::Output as PsbtOut},
         Amount, PublicKey, Script, TxOut,
     },
@@ -17,6 +21,12 @@ use miniscript::{
 
 use std::{collections::BTreeMap, fmt};
 
+/// Maximum size of the data pushed in a standard null-data (OP_RETURN) output. Bitcoin Core's
+/// 83 bytes limit applies to the whole Script, including the OP_RETURN and push opcodes.
+///
+/// <https://github.com/bitcoin/bitcoin/blob/590e49ccf2af27c6c1f1e0eb8be3a4bf4d92ce8b/src/script/standard.h#L68>
+pub const MAX_OP_RETURN_DATA: usize = 80;
+
 /// Map of a raw public key to the xpub used to derive it and its derivation path
 pub type Bip32Deriv = BTreeMap<PublicKey, (bip32::Fingerprint, bip32::DerivationPath)>;
 
@@ -223,6 +233,23 @@ impl SpendTxOut {
     pub fn new(txo: TxOut) -> Self {
         SpendTxOut(txo)
     }
+
+    /// Create a null-data output, with a value of 0, embedding this `data` (e.g. an audit tag).
+    /// Will error if `data` is larger than [MAX_OP_RETURN_DATA] bytes, as it would not be
+    /// standard.
+    pub fn op_return(data: &[u8]) -> Result<Self, TxoutCreationError> {
+        if data.len() > MAX_OP_RETURN_DATA {
+            return Err(TxoutCreationError::OpReturnTooLarge(data.len()));
+        }
+
+        Ok(SpendTxOut(TxOut {
+            value: 0,
+            script_pubkey: script::Builder::new()
+                .push_opcode(opcodes::all::OP_RETURN)
+                .push_slice(data)
+                .into_script(),
+        }))
+    }
 }
 
 impl RevaultTxOut for SpendTxOut {